@@ -19,10 +19,6 @@ pkgs.rustPlatform.buildRustPackage rec {
 
     # Highly recommended for users of zellij:
     zellij
-    # Highly recommended for users of terminals in neovim:
-    neovim-remote
-    # Recommended for users of ag -- another option is ripgrep, just replace ag with rg in voxide config:
-    silver-searcher 
   ];
 
   version = "0.1.0"; # should match what's in Cargo.toml
@@ -16,9 +16,7 @@ if submode == 'r'
   # TODO broken because different TSV columns for fzf
   system 'rails-routes-cached'
 elsif (dir = DIRS[submode.to_sym])
-  ARGV[0] = dir
-  ARGV[1] ||= ''
-  require_relative 'voxide-file-acronym.rb'
+  exec 'voxide', '--builtin', 'acronym', dir, ARGV[1] || ''
 else
   raise "unknown submode, expected one of #{DIRS.keys.join(', ')} or 'r'"
 end
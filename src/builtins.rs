@@ -0,0 +1,91 @@
+use crate::{
+    config_finder, content_search, file_acronym, file_walker, git_files, history, locations,
+    nvim_rpc, nvim_shada,
+};
+use color_eyre::eyre::{eyre, Result};
+
+/// A script (or cmd) starting with this runs a source built into voxide, e.g.
+/// `script = "builtin:history"`. Builtins run as `voxide --builtin <name> <args...>`, so they can
+/// be used anywhere a command can (with filters, timeouts, etc.).
+pub const BUILTIN_PREFIX: &str = "builtin:";
+
+/// Builtin names (kept sorted), with descriptions for --help
+pub const BUILTINS: [(&str, &str); 9] = [
+    (
+        "acronym",
+        "[<dir or path regex>] [<acronym>]: files whose path acronyms best match, e.g. AMcetR or cet for app/models/context_external_tool.rb",
+    ),
+    (
+        "config",
+        "[--root <dir>]... [<name>|- [<sub-path>|-]]: where a program's config is (~/.<name>, ~/.<name>rc, ~/.config/<name>/...), or candidates to choose from",
+    ),
+    (
+        "find",
+        "[-H] [-I] [-L] [-t f|d|l] [-e <ext>] [-d <depth>] [-g] [-F] [-p] [-s|-i] [<pattern>] [<dir>...]: files with names matching the regex (or glob), like fd, printed as a parallel walk finds them",
+    ),
+    (
+        "git-files",
+        "[--raw | --numbered <output file>] [<ref>]: files changed in the worktree, index and last commit (or in <ref>), as <path>\\t<display> lines for fzf",
+    ),
+    (
+        "grep",
+        "[-F] [-w] [-s|-i] [-g <glob>]... [-H] [-I] [-a] <pattern> [<path>...]: lines matching the regex, as path:line:column:text for a quickfix list, like ag",
+    ),
+    (
+        "history",
+        "[--all] [--lines] [<regex>]: files opened with voxide in this repository (or directory), most frecent first",
+    ),
+    (
+        "locations",
+        "<name> [<query>]: entries in the directories of [locations.<name>] in the config",
+    ),
+    (
+        "nvim",
+        "[--server <address>] [--previous] [--split|--vsplit|--tab|--background] [--quit] [-q <quickfix file>] [--] <files...>: open in the running Neovim ($NVIM), for use as a cmd",
+    ),
+    (
+        "nvim-oldfiles",
+        "[--all] [--no-lines] [--file <shada>] [<regex>]: Neovim's recent files (from its ShaDa file) in this repository (or directory), with the last cursor line",
+    ),
+];
+
+/// The builtin a command refers to, if any
+pub fn builtin_name(command: &str) -> Option<&str> {
+    command.strip_prefix(BUILTIN_PREFIX)
+}
+
+/// Runs a builtin, printing its output (for --builtin)
+pub fn run_builtin(name: &str, args: &[String]) -> Result<()> {
+    match name {
+        "acronym" => file_acronym::acronym_source(args),
+        "config" => config_finder::config_source(args),
+        "find" => file_walker::find_source(args),
+        "git-files" => git_files::git_files_source(args),
+        "grep" => content_search::grep_source(args),
+        "history" => history::history_source(args),
+        "locations" => locations::locations_source(args),
+        "nvim" => nvim_rpc::nvim_source(args),
+        "nvim-oldfiles" => nvim_shada::oldfiles_source(args),
+        _ => Err(eyre!(
+            "Unknown builtin {BUILTIN_PREFIX}{name} (expected one of: {})",
+            BUILTINS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtins_sorted() {
+        let names = BUILTINS.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        let mut sorted = names.clone();
+        sorted.sort_unstable();
+        assert_eq!(names, sorted);
+    }
+}
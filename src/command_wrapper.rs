@@ -1,13 +1,45 @@
-use crate::{config::Script, path_util};
+use crate::{
+    builtins::builtin_name,
+    config::{Mode, Script},
+    path_util,
+    transforms_replacement_preprocessor::expand_placeholders,
+};
 use color_eyre::eyre::{eyre, OptionExt, Result, WrapErr};
 use log::debug;
 use std::{
+    ffi::OsString,
     fs::File,
+    io::Read,
     path::Path,
-    process::{Command, Stdio},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
 };
 use tempfile::NamedTempFile;
 
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long a stage being stopped has to exit (e.g. for fzf to restore the terminal) before it's
+/// killed
+const STOP_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+/// Set by the SIGINT handler installed while waiting on a stage with a timeout, so voxide stops
+/// the stages itself rather than exiting and leaving them running
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_signum: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// A process that has been spawned and is piping into a later command (see `thru_filter`). It is
+/// waited on (and its timeout enforced) along with the last command in the pipeline.
+#[derive(Debug)]
+struct Upstream {
+    child: Child,
+    stage: String,
+    timeout: Option<Duration>,
+}
+
 /// Wrapper around std::process::Command to make it easier to use (supporting running scripts from
 /// a string, through a tempfile) and to run commands as specified in config.
 /// Many of the functions pass through to std::process::Command functions.
@@ -18,6 +50,12 @@ pub struct CommandWrapper {
     // in the config file or using a builtin scrpit (built-in to voxide binary). When filters are
     // used, multipl tempfiles may be needed.
     _tempfiles: Vec<tempfile::TempPath>,
+    // Name of this stage (e.g. "script", "filter") used in error messages
+    stage: String,
+    timeout: Option<Duration>,
+    upstream: Vec<Upstream>,
+    // Environment variables also passed to the filters this command is piped into
+    pipeline_env: Vec<(OsString, OsString)>,
 }
 
 impl CommandWrapper {
@@ -35,11 +73,25 @@ impl CommandWrapper {
     }
 
     pub fn new_from_script_path(cmd: &str) -> Result<CommandWrapper> {
-        let mut command = Self::std_process_command_new(cmd.as_ref());
+        let mut command = match builtin_name(cmd) {
+            Some(name) => {
+                let mut command = Command::new(std::env::current_exe()?);
+                command
+                    .args(path_util::config_override_args())
+                    .arg("--builtin")
+                    .arg(name);
+                command
+            }
+            None => Self::std_process_command_new(cmd.as_ref()),
+        };
         command.env("PATH", path_util::path_with_prepended_script_paths());
         Ok(Self {
             command,
             _tempfiles: vec![],
+            stage: "script".to_owned(),
+            timeout: None,
+            upstream: vec![],
+            pipeline_env: vec![],
         })
     }
 
@@ -65,6 +117,27 @@ impl CommandWrapper {
         self
     }
 
+    pub fn env<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where
+        K: AsRef<std::ffi::OsStr>,
+        V: AsRef<std::ffi::OsStr>,
+    {
+        self.command.env(key, value);
+        self
+    }
+
+    /// Like `env`, but also for the filters this command is piped into (see `thru_filter`)
+    pub fn pipeline_env<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where
+        K: Into<OsString>,
+        V: Into<OsString>,
+    {
+        let (key, value) = (key.into(), value.into());
+        self.command.env(&key, &value);
+        self.pipeline_env.push((key, value));
+        self
+    }
+
     pub fn args<T>(&mut self, args: impl IntoIterator<Item = T>) -> &mut Self
     where
         T: AsRef<std::ffi::OsStr>,
@@ -73,6 +146,24 @@ impl CommandWrapper {
         self
     }
 
+    /// Stop this stage (see `stop`) if it runs for longer than `timeout`
+    pub fn timeout(&mut self, stage: &str, timeout: Option<Duration>) -> &mut Self {
+        self.stage = stage.to_owned();
+        self.timeout = timeout;
+        self
+    }
+
+    /// Apply the mode's `env` and `cwd`, expanding placeholders such as ${gitroot}
+    pub fn mode_env(&mut self, mode: &Mode) -> &mut Self {
+        for (key, value) in mode.env.iter().flatten() {
+            self.command.env(key, expand_placeholders(value));
+        }
+        if let Some(cwd) = &mode.cwd {
+            self.command.current_dir(expand_placeholders(cwd));
+        }
+        self
+    }
+
     pub fn stdout_to_tempfile(&mut self, tempfile: &NamedTempFile) -> Result<&mut Self> {
         self.command
             .stdout(Stdio::from(File::create(tempfile.path())?));
@@ -80,48 +171,83 @@ impl CommandWrapper {
     }
 
     // Pass this command's stdout to another command's stdin, and return wrapper for the second
-    pub fn thru_filter(mut self, filter: &Option<Script>) -> Result<Self> {
-        if let Some(ref filter) = filter {
-            debug!("Running command as filter: {:?}", self.command);
-            let script_output = self.command.stdout(Stdio::piped()).spawn()?;
-            let mut filter_wrapper = Self::new(filter)?;
-            filter_wrapper.command.stdin(
-                script_output
-                    .stdout
-                    .ok_or_eyre("failed to open stdout in pipe")?,
-            );
-
-            // need to make all temp files last until the last command is done,
-            // so need to keep track of all _tempfiles
-            self._tempfiles.append(&mut filter_wrapper._tempfiles);
-            filter_wrapper._tempfiles = self._tempfiles;
-            return Ok(filter_wrapper);
+    // (and through any filters from earlier modes chained before it)
+    pub fn thru_filter(self, mode: &Mode) -> Result<Self> {
+        let mut wrapper = self;
+        for prior_filter in &mode.prior_filters {
+            wrapper = wrapper.pipe_into(&prior_filter.script, prior_filter.timeout, mode)?;
         }
+        match mode.filter {
+            Some(ref filter) => wrapper.pipe_into(filter, mode.filter_timeout, mode),
+            None => Ok(wrapper),
+        }
+    }
 
-        Ok(self)
+    fn pipe_into(mut self, filter: &Script, timeout: Option<u64>, mode: &Mode) -> Result<Self> {
+        debug!("Running command as filter: {:?}", self.command);
+        let mut script_child = self.command.stdout(Stdio::piped()).spawn()?;
+        let mut filter_wrapper = Self::new(filter)?;
+        filter_wrapper
+            .timeout("filter", timeout.map(Duration::from_secs))
+            .mode_env(mode);
+        for (key, value) in &self.pipeline_env {
+            filter_wrapper.env(key, value);
+        }
+        filter_wrapper.pipeline_env = self.pipeline_env;
+        filter_wrapper.command.stdin(
+            script_child
+                .stdout
+                .take()
+                .ok_or_eyre("failed to open stdout in pipe")?,
+        );
+        filter_wrapper.upstream = self.upstream;
+        filter_wrapper.upstream.push(Upstream {
+            child: script_child,
+            stage: self.stage,
+            timeout: self.timeout,
+        });
+
+        // need to make all temp files last until the last command is done,
+        // so need to keep track of all _tempfiles
+        self._tempfiles.append(&mut filter_wrapper._tempfiles);
+        filter_wrapper._tempfiles = self._tempfiles;
+        Ok(filter_wrapper)
     }
 
     pub fn run_get_output(&mut self) -> Result<String> {
         debug!("Running command and getting output: {:?}", self.command);
-        let spawned = self
+        let mut spawned = self
             .command
             .stdout(Stdio::piped())
             .spawn()
             .wrap_err("Failed to spawn script")?;
 
-        let res = spawned.wait_with_output()?;
-        if !res.status.success() {
-            Err(eyre!("Script exited with status {}", res.status))
+        // Read in a separate thread so a chatty script can't block on a full pipe while we poll
+        let mut stdout = spawned
+            .stdout
+            .take()
+            .ok_or_eyre("failed to open stdout in pipe")?;
+        let reader = thread::spawn(move || {
+            let mut buf = vec![];
+            stdout.read_to_end(&mut buf).map(|_| buf)
+        });
+
+        let status = self.wait(spawned)?;
+        let stdout = reader
+            .join()
+            .map_err(|_| eyre!("Failed to read output of {}", self.stage))??;
+        if !status.success() {
+            Err(eyre!("Script exited with status {}", status))
         } else {
-            Ok(String::from_utf8(res.stdout)?)
+            Ok(String::from_utf8(stdout)?)
         }
     }
 
     pub fn run(&mut self) -> Result<()> {
         debug!("Running command: {:?}", self.command);
-        let mut spawned = self.command.spawn().wrap_err("Failed to spawn script")?;
-        let res = spawned
-            .wait()?
+        let spawned = self.command.spawn().wrap_err("Failed to spawn script")?;
+        let res = self
+            .wait(spawned)?
             .code()
             .ok_or(eyre!("Process killed by signal"))?;
 
@@ -131,4 +257,90 @@ impl CommandWrapper {
             Ok(())
         }
     }
+
+    /// Waits for `child`, enforcing its timeout and those of any upstream processes piping into it.
+    /// Returns the exit status of `child`.
+    fn wait(&mut self, mut child: Child) -> Result<ExitStatus> {
+        let has_timeout =
+            self.timeout.is_some() || self.upstream.iter().any(|u| u.timeout.is_some());
+        if !has_timeout {
+            return Ok(child.wait()?);
+        }
+
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        let handler = on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+        let prev_handler = unsafe { libc::signal(libc::SIGINT, handler) };
+        let res = self.poll_until_done(&mut child);
+        // SAFETY: restoring the handler we replaced above
+        unsafe { libc::signal(libc::SIGINT, prev_handler) };
+
+        if res.is_err() {
+            // Pass on a Ctrl-C (which the stages also get from the terminal, unless voxide wasn't
+            // run from one)
+            let signal = if INTERRUPTED.load(Ordering::SeqCst) {
+                libc::SIGINT
+            } else {
+                libc::SIGTERM
+            };
+            stop(&mut child, signal);
+            for upstream in &mut self.upstream {
+                stop(&mut upstream.child, signal);
+            }
+        }
+        res
+    }
+
+    fn poll_until_done(&mut self, child: &mut Child) -> Result<ExitStatus> {
+        let start = Instant::now();
+        let timed_out = |timeout: Option<Duration>| timeout.is_some_and(|t| start.elapsed() > t);
+
+        loop {
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                return Err(eyre!("{} interrupted", self.stage));
+            }
+
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+
+            for upstream in &mut self.upstream {
+                if upstream.child.try_wait()?.is_none() && timed_out(upstream.timeout) {
+                    return Err(timeout_error(&upstream.stage, upstream.timeout));
+                }
+            }
+            if timed_out(self.timeout) {
+                return Err(timeout_error(&self.stage, self.timeout));
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+fn timeout_error(stage: &str, timeout: Option<Duration>) -> color_eyre::Report {
+    eyre!(
+        "{stage} timed out after {}s",
+        timeout.unwrap_or_default().as_secs()
+    )
+}
+
+/// Stops a child process: sends it `signal`, then kills it if it hasn't exited after
+/// `STOP_GRACE_PERIOD`. Only the child itself is signalled, since it stays in the terminal's
+/// foreground process group (so a filter like fzf can use the terminal); anything it spawned exits
+/// when its pipes close.
+fn stop(child: &mut Child, signal: libc::c_int) {
+    if let (Ok(None), Ok(pid)) = (child.try_wait(), libc::pid_t::try_from(child.id())) {
+        // SAFETY: kill has no memory safety requirements, and the child hasn't been reaped
+        unsafe { libc::kill(pid, signal) };
+        let start = Instant::now();
+        while start.elapsed() < STOP_GRACE_PERIOD {
+            if !matches!(child.try_wait(), Ok(None)) {
+                return;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
 }
@@ -0,0 +1,392 @@
+use crate::command_wrapper::CommandWrapper;
+use crate::config::{get_config, AppConfig, Mode, Script};
+use crate::help::{mode_sort_key, mode_spec_for_name};
+use crate::mode_args::ArgSpec;
+use crate::shell_init;
+use color_eyre::eyre::{eyre, Result};
+use std::time::Duration;
+
+/// Shells `--completions` can generate a completion script for
+pub const SHELLS: [&str; 4] = ["bash", "zsh", "fish", "tabry"];
+
+/// Flags accepted as the first argument, with descriptions
+const FLAGS: [(&str, &str); 11] = [
+    ("--help", "show help, or help for a mode"),
+    ("--mode", "use a mode or alias by name"),
+    ("--config", "use this config dir or file"),
+    ("--init", "install the default config and scripts"),
+    ("--trust", "trust the project configs found from here"),
+    ("--check-config", "check the config for errors"),
+    ("--print-schema", "print a JSON Schema for config files"),
+    ("--config-sources", "list the config files used"),
+    ("--completions", "print a shell completion script"),
+    (
+        "--init-shell",
+        "print shell functions, keybinding and completions",
+    ),
+    ("--readme", "print the README"),
+];
+
+/// Complete scripts are killed after this long so a slow one doesn't hang the shell
+const COMPLETE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq)]
+pub struct Candidate {
+    pub value: String,
+    pub desc: String,
+}
+
+impl Candidate {
+    fn new(value: impl Into<String>, desc: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+            desc: desc.into(),
+        }
+    }
+}
+
+/// Candidates for a mode spec (the first argument) starting with `prefix`: the prefix itself if it
+/// is a valid spec, then each one-letter mode/alias appended, and `:name`s. After a `:`, the
+/// partial long name is completed instead.
+fn mode_spec_candidates(config: &AppConfig, prefix: &str) -> Vec<Candidate> {
+    let names = names_with_descs(config);
+    if let Some(colon) = prefix.rfind(':') {
+        let (before, partial) = (&prefix[..colon], &prefix[colon + 1..]);
+        return names
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(partial))
+            .map(|(name, desc)| Candidate::new(format!("{before}:{name}"), desc))
+            .collect();
+    }
+
+    let mut candidates = vec![];
+    if !prefix.is_empty() {
+        if let Ok(merged) = config.merged_mode(Some(prefix)) {
+            candidates.push(Candidate::new(prefix, merged.mode.name));
+        }
+    }
+    candidates.extend(names.into_iter().map(|(name, desc)| {
+        Candidate::new(format!("{prefix}{}", mode_spec_for_name(&name)), desc)
+    }));
+    candidates
+}
+
+/// All mode and alias names with short descriptions, single letters first
+fn names_with_descs(config: &AppConfig) -> Vec<(String, String)> {
+    let mut names = config
+        .modes
+        .iter()
+        .map(|(name, mode)| (name.clone(), mode.name.clone()))
+        .chain(
+            config
+                .aliases
+                .iter()
+                .map(|(name, spec)| (name.clone(), format!("alias for {spec}"))),
+        )
+        .collect::<Vec<_>>();
+    names.sort_by_key(|(name, _)| mode_sort_key(name));
+    names
+}
+
+/// The declared argument at `position` among the script's arguments, if any
+fn arg_spec_at(mode: &Mode, position: usize) -> Option<&ArgSpec> {
+    let specs = mode.arg_spec.as_ref()?;
+    specs
+        .get(position)
+        .or_else(|| specs.last().filter(|spec| spec.rest))
+}
+
+/// Runs the mode's `complete` script with the script arguments so far (the last being the word
+/// being completed). It prints one candidate per line, optionally followed by a tab and a
+/// description.
+fn run_complete_script(mode: &Mode, complete: &Script, words: &[String]) -> Result<Vec<Candidate>> {
+    let mut command = CommandWrapper::new(complete)?;
+    command
+        .args(words)
+        .timeout("complete", Some(COMPLETE_TIMEOUT))
+        .mode_env(mode);
+    Ok(command
+        .run_get_output()?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once('\t') {
+            Some((value, desc)) => Candidate::new(value, desc),
+            None => Candidate::new(line, ""),
+        })
+        .collect())
+}
+
+/// Candidates for an argument to the mode's script: from its `complete` script, or else the
+/// `choices` of the declared argument. No candidates means the shell should complete files.
+fn script_arg_candidates(
+    config: &AppConfig,
+    spec: &str,
+    words: &[String],
+) -> Result<Vec<Candidate>> {
+    let mode = config.merged_mode(Some(spec))?.mode;
+    if let Some(complete) = &mode.complete {
+        return run_complete_script(&mode, complete, words);
+    }
+    let Some(arg) = arg_spec_at(&mode, words.len() - 1) else {
+        return Ok(vec![]);
+    };
+    Ok(arg
+        .choices
+        .iter()
+        .flatten()
+        .map(|choice| Candidate::new(choice, arg.desc.clone().unwrap_or_default()))
+        .collect())
+}
+
+/// Completion candidates for the command line `words` (not including the command itself), the
+/// last of which is the word being completed
+pub fn candidates(config: &AppConfig, words: &[String]) -> Result<Vec<Candidate>> {
+    let (current, before) = words
+        .split_last()
+        .ok_or_else(|| eyre!("Nothing to complete"))?;
+    let candidates = match before {
+        [] if current.starts_with('-') => FLAGS
+            .iter()
+            .map(|(flag, desc)| Candidate::new(*flag, *desc))
+            .collect(),
+        [] => mode_spec_candidates(config, current),
+        [flag] if flag == "--mode" => names_with_descs(config)
+            .into_iter()
+            .map(|(name, desc)| Candidate::new(name, desc))
+            .collect(),
+        [flag] if flag == "--help" => mode_spec_candidates(config, current),
+        [flag] if flag == "--completions" => SHELLS
+            .iter()
+            .map(|shell| Candidate::new(*shell, ""))
+            .collect(),
+        [flag] if flag == "--init-shell" => shell_init::SHELLS
+            .iter()
+            .map(|shell| Candidate::new(*shell, ""))
+            .collect(),
+        [flag, name, ..] if flag == "--mode" => {
+            script_arg_candidates(config, &format!(":{name}"), &words[2..])?
+        }
+        [flag, ..] if flag.starts_with('-') => vec![],
+        [spec, ..] => script_arg_candidates(config, spec, &words[1..])?,
+    };
+    Ok(candidates
+        .into_iter()
+        .filter(|candidate| candidate.value.starts_with(current.as_str()))
+        .collect())
+}
+
+/// Prints completion candidates for the command line `words` as "<value>\t<desc>" lines (for
+/// --complete, which the generated completion scripts call)
+pub fn complete(words: &[String]) -> Result<()> {
+    let config = get_config()?;
+    for candidate in candidates(&config, words)? {
+        if candidate.desc.is_empty() {
+            println!("{}", candidate.value);
+        } else {
+            println!("{}\t{}", candidate.value, candidate.desc);
+        }
+    }
+    Ok(())
+}
+
+const BASH: &str = r#"_voxide_complete() {
+    local line="${COMP_LINE:0:COMP_POINT}" words IFS=$' \t\n'
+    read -ra words <<< "$line"
+    [[ $line == *[[:space:]] ]] && words+=("")
+    local cur="${words[${#words[@]}-1]}"
+    local IFS=$'\n'
+    COMPREPLY=($(voxide --complete "${words[@]:1}" 2>/dev/null | cut -f1))
+    # Bash only replaces the part of the word after the last ':'
+    if [[ $cur == *:* && $COMP_WORDBREAKS == *:* ]]; then
+        local colon_prefix="${cur%"${cur##*:}"}"
+        COMPREPLY=("${COMPREPLY[@]#"$colon_prefix"}")
+    fi
+}
+complete -o default -F _voxide_complete __NAME__
+"#;
+
+const ZSH: &str = r#"#compdef __NAME__
+_voxide_complete() {
+    local -a lines described
+    local line value desc
+    lines=("${(@f)$(voxide --complete "${(@)words[2,CURRENT]}" 2>/dev/null)}")
+    for line in $lines; do
+        [[ -z $line ]] && continue
+        value="${line%%$'\t'*}"
+        desc=""
+        [[ $line == *$'\t'* ]] && desc="${line#*$'\t'}"
+        described+=("${value//:/\\:}:$desc")
+    done
+    if (( ${#described} )); then
+        _describe -V voxide described
+    else
+        _files
+    fi
+}
+# compdef is only available once the completion system is loaded (compinit)
+(( $+functions[compdef] )) && compdef _voxide_complete __NAME__
+"#;
+
+const FISH: &str = r#"function __voxide_complete
+    voxide --complete (commandline -opc)[2..-1] (commandline -ct) 2>/dev/null
+end
+"#;
+
+/// Registers fish completions for one command name
+const FISH_COMMAND: &str = r#"complete -c __NAME__ -f -n 'test (count (commandline -opc)) -eq 1' -a '(__voxide_complete)'
+complete -c __NAME__ -n 'test (count (commandline -opc)) -gt 1' -a '(__voxide_complete)'
+"#;
+
+/// Quotes a string for tabry
+fn tabry_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// tabry can't call back into voxide for the first argument, so each mode (and alias) is a
+/// subcommand; combinations of letters aren't completed.
+fn tabry(config: &AppConfig, name: &str) -> String {
+    let mut out = format!("# Generated by 'voxide --completions tabry {name}'\n\ncmd {name}\n");
+    for (mode_name, desc) in names_with_descs(config) {
+        let spec = mode_spec_for_name(&mode_name);
+        let body = match config.merged_mode(Some(&spec)) {
+            Ok(merged) => tabry_mode_args(&merged.mode, &spec),
+            Err(_) => String::new(),
+        };
+        out.push_str(&format!(
+            "\nsub {} {}",
+            tabry_string(&spec),
+            tabry_string(&desc)
+        ));
+        if body.is_empty() {
+            out.push('\n');
+        } else {
+            out.push_str(&format!(" {{\n{body}}}\n"));
+        }
+    }
+    out
+}
+
+/// tabry args for a mode's script: a `complete` script (called through voxide), or else the
+/// declared arguments
+fn tabry_mode_args(mode: &Mode, spec: &str) -> String {
+    if mode.complete.is_some() {
+        let command = format!("voxide --complete {spec} ''");
+        return format!(
+            "  varargs {{\n    opts shell {}\n  }}\n",
+            tabry_string(&command)
+        );
+    }
+    let mut out = String::new();
+    for arg in mode.arg_spec.iter().flatten() {
+        let kind = match (arg.rest, arg.required) {
+            (true, _) => "varargs",
+            (false, true) => "arg",
+            (false, false) => "opt arg",
+        };
+        let opts = match &arg.choices {
+            Some(choices) => {
+                let choices = choices.iter().map(|choice| tabry_string(choice));
+                format!("opts const ({})", choices.collect::<Vec<_>>().join(" "))
+            }
+            None => "opts file".to_owned(),
+        };
+        out.push_str(&format!(
+            "  {kind} {} {} {{\n    {opts}\n  }}\n",
+            arg.name,
+            tabry_string(arg.desc.as_deref().unwrap_or(""))
+        ));
+    }
+    out
+}
+
+/// Completion script for `shell`, registered for the commands `names` (the first of which is used
+/// for tabry)
+pub fn completion_script(shell: &str, names: &[&str]) -> Result<String> {
+    let joined = names.join(" ");
+    Ok(match shell {
+        "bash" => BASH.replace("__NAME__", &joined),
+        "zsh" => ZSH.replace("__NAME__", &joined),
+        "fish" => names.iter().fold(FISH.to_owned(), |script, name| {
+            script + &FISH_COMMAND.replace("__NAME__", name)
+        }),
+        "tabry" => tabry(&get_config()?, names[0]),
+        _ => {
+            return Err(eyre!(
+                "Unknown shell {shell} (expected one of: {})",
+                SHELLS.join(", ")
+            ))
+        }
+    })
+}
+
+/// Prints a completion script for `shell`, registered for the command `name` (for --completions)
+pub fn print_completions(shell: &str, name: &str) -> Result<()> {
+    print!("{}", completion_script(shell, &[name])?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    const COMPLETION_MODES: &str = r#"
+        [modes.g]
+        arg_spec = [{ name = "kind", desc = "which files", choices = ["staged", "commit"] }]
+        [modes.rails-routes]
+        complete = ["sh", "-c", "printf 'users#index\tUsers\nposts#show\n'", "sh"]
+        [aliases]
+        review = "gz"
+    "#;
+
+    fn values(config: &AppConfig, words: &[&str]) -> Vec<String> {
+        let words = words
+            .iter()
+            .map(|word| word.to_string())
+            .collect::<Vec<_>>();
+        candidates(config, &words)
+            .unwrap()
+            .into_iter()
+            .map(|candidate| candidate.value)
+            .collect()
+    }
+
+    #[test]
+    fn test_mode_spec_candidates() -> Result<()> {
+        let config = test_config(COMPLETION_MODES)?;
+        assert_eq!(
+            values(&config, &[""]),
+            vec!["g", "z", ":rails-routes", ":review"]
+        );
+        assert_eq!(
+            values(&config, &["g"]),
+            vec!["g", "gg", "gz", "g:rails-routes", "g:review"]
+        );
+        assert_eq!(
+            values(&config, &["z:r"]),
+            vec!["z:rails-routes", "z:review"]
+        );
+        assert_eq!(values(&config, &["--mode", "rev"]), vec!["review"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_script_arg_candidates() -> Result<()> {
+        let config = test_config(COMPLETION_MODES)?;
+        assert_eq!(values(&config, &["zg", "s"]), vec!["staged"]);
+        assert_eq!(values(&config, &["g", "staged", ""]), Vec::<String>::new());
+        assert_eq!(
+            candidates(
+                &config,
+                &[
+                    "--mode".to_owned(),
+                    "rails-routes".to_owned(),
+                    "u".to_owned()
+                ]
+            )
+            .unwrap(),
+            vec![Candidate::new("users#index", "Users")]
+        );
+        Ok(())
+    }
+}
@@ -1,43 +1,131 @@
-use crate::path_util::config_dir;
-use color_eyre::eyre::{eyre, OptionExt, Result};
+use crate::config_include::resolve_includes;
+use crate::locations::LocationSet;
+use crate::mode_args::{validate_arg_spec, ArgSpec};
+use crate::mode_merge::{merge_modes, MergeConfig, MergedMode};
+use crate::path_util::config_paths;
+use crate::project_config::trusted_project_configs;
+use crate::transforms_replacement_preprocessor::expand_placeholders;
+use color_eyre::eyre::{eyre, OptionExt, Result, WrapErr};
 use figment::{
-    providers::{Format, Toml},
-    Figment,
+    providers::{Format, Json, Toml, Yaml},
+    value::{Dict, Map},
+    Figment, Metadata, Profile, Provider,
 };
 use log::debug;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs::DirEntry;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct AppConfig {
-    pub modes: HashMap<char, Mode>,
+    /// Modes by name. One-character names can be combined in the first argument (e.g. `gz`);
+    /// longer names are given as `:name`.
+    pub modes: HashMap<String, Mode>,
+    /// Names that expand to a mode spec, e.g. `review = "gq"`
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Regex replacements ([from, to]) applied to filenames until one exists
     pub transforms: Vec<(String, String)>,
+    /// The base mode which all other modes are merged onto
     pub default_mode: Mode,
+    /// Mode letter(s) merged in (before any given on the command line) when stdin is piped.
+    pub stdin_mode: Option<String>,
+    /// Named sets of directories, listed by `builtin:locations <name>`
+    #[serde(default)]
+    pub locations: HashMap<String, LocationSet>,
+    /// Record the files opened in the history used by `builtin:history`
+    #[serde(default = "default_true")]
+    pub record_history: bool,
+    /// How each field is merged when combining modes
+    #[serde(default)]
+    pub merge: MergeConfig,
+    /// Other config files to load before this one (paths or globs, relative to this file)
+    // Read from each file by config_include; here so it's in the schema
+    #[allow(dead_code)]
+    #[serde(default)]
+    pub include: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct Mode {
     pub name: String,
+    /// Description for --help. Only the first line is shown in the list of modes.
     pub desc: String,
+    /// Heading the mode is listed under in --help
+    pub category: Option<String>,
+    /// Example invocations, shown by `voxide --help <mode>`
+    pub examples: Option<Vec<String>>,
+    /// Script which outputs the files to open
     pub script: Option<Script>,
+    /// Command the script's output is piped through (e.g. fzf)
     pub filter: Option<Script>,
+    /// Pass a tempfile as the script's first argument, and read the files from it
     pub script_uses_tempfile: Option<bool>,
     // TODO: make cmd, etc. like script probably where it can be an array
+    /// Command to open files with
     pub cmd: Option<String>,
+    /// Command to open a quickfix file with
     pub quickfix_cmd: Option<String>,
+    /// Command to open a directory with (defaults to cmd)
     pub dir_cmd: Option<String>,
+    /// The script outputs a quickfix list rather than files
     pub quickfix: Option<bool>,
+    /// Seconds after which the script is killed
+    pub timeout: Option<u64>,
+    /// Seconds after which the filter is killed
+    pub filter_timeout: Option<u64>,
+    /// Environment variables for the script, filter and cmd. Values may use ${gitroot}/${home}.
+    pub env: Option<HashMap<String, String>>,
+    /// Working directory for the script, filter and cmd. May use ${gitroot}/${home}.
+    pub cwd: Option<String>,
+    /// Name of a mode to inherit unset fields from (resolved when the config is loaded)
+    pub extends: Option<String>,
+    /// Arguments passed to the script before those given on the command line
+    pub args: Option<Vec<String>>,
+    /// The arguments the script takes from the command line, which are checked before it runs
+    pub arg_spec: Option<Vec<ArgSpec>>,
+    /// Command that prints completions for the script's arguments (see --completions)
+    pub complete: Option<Script>,
+    /// Filters from earlier modes to run before `filter`, when merging filters with "append"
+    #[serde(skip)]
+    pub prior_filters: Vec<PriorFilter>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PriorFilter {
+    pub script: Script,
+    pub timeout: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum Script {
+    /// A command (script name in PATH or path) with no arguments
     Command(String),
+    /// A command followed by its arguments
     CommandWithArgs(Vec<String>),
 }
 
 impl Mode {
+    /// Expands placeholders in `cwd` and makes it absolute, so it still refers to the same
+    /// directory once voxide has changed into it (before running the cmd)
+    pub fn resolve_cwd(&mut self) -> Result<()> {
+        if let Some(cwd) = &self.cwd {
+            let resolved = std::path::absolute(expand_placeholders(cwd))
+                .wrap_err_with(|| format!("Invalid mode cwd {cwd}"))?;
+            self.cwd = Some(resolved.to_string_lossy().into_owned());
+        }
+        Ok(())
+    }
+
     pub fn cmd_for_isdir_and_qf(&self, is_dir: bool, is_quickfix: bool) -> Result<&String> {
         match (is_dir, is_quickfix) {
             (_isdir, true) => self
@@ -55,93 +143,822 @@ impl Mode {
                 .ok_or_eyre("No cmd found in combined modes"),
         }
     }
+}
 
-    // Merge other into self, preferring self's values
-    fn reverse_merge(&mut self, other: &Mode) {
-        if let (None, Some(other_script)) = (&self.script, &other.script) {
-            // there can be a max of script/script_with_tempfile
-            self.script = Some(other_script.clone());
-            self.quickfix = other.quickfix;
-            self.script_uses_tempfile = other.script_uses_tempfile;
+impl AppConfig {
+    /// Merges each mode with the mode it `extends` (recursively). `figment` is used to find which
+    /// config file defined a mode, for error messages.
+    fn resolve_extends(&mut self, figment: &Figment) -> Result<()> {
+        let mut resolved = HashMap::new();
+        for name in self.modes.keys() {
+            self.resolve_mode_extends(name, figment, &mut vec![], &mut resolved)?;
         }
+        self.modes = resolved;
+        Ok(())
+    }
 
-        if let (None, Some(cmd)) = (&self.cmd, &other.cmd) {
-            self.cmd = Some(cmd.clone());
+    fn resolve_mode_extends(
+        &self,
+        name: &str,
+        figment: &Figment,
+        chain: &mut Vec<String>,
+        resolved: &mut HashMap<String, Mode>,
+    ) -> Result<Mode> {
+        if let Some(mode) = resolved.get(name) {
+            return Ok(mode.clone());
         }
+        let mut mode = self.get_mode(name)?.clone();
+        if let Some(parent) = mode.extends.take() {
+            let source = mode_source(figment, name);
+            if chain.contains(&parent) {
+                return Err(eyre!(
+                    "Cycle in mode `extends`: {} -> {name} -> {parent} (mode {name} defined in {source})",
+                    chain.join(" -> ")
+                ));
+            }
+            if !self.modes.contains_key(&parent) {
+                return Err(eyre!(
+                    "Mode {name} extends unknown mode {parent} (defined in {source})"
+                ));
+            }
+            chain.push(name.to_owned());
+            let parent_mode = self.resolve_mode_extends(&parent, figment, chain, resolved)?;
+            chain.pop();
+            let mut merged = parent_mode;
+            merged.merge(&mode, &self.merge.onto_fallback());
+            merged.name = mode.name;
+            merged.desc = mode.desc;
+            merged.examples = mode.examples;
+            merged.category = mode.category.or(merged.category);
+            mode = merged;
+        }
+        resolved.insert(name.to_owned(), mode.clone());
+        Ok(mode)
+    }
 
-        if let (None, Some(dir_cmd)) = (&self.dir_cmd, &other.dir_cmd) {
-            self.dir_cmd = Some(dir_cmd.clone());
+    /// Merges the modes in `letters` onto the default mode, returning any conflicts rather than
+    /// warning about them
+    pub fn merged_mode(&self, letters: Option<&str>) -> Result<MergedMode> {
+        self.merged_mode_with_stdin(letters, false)
+    }
+
+    /// Like `merged_mode`, but if `stdin_mode_prepended` (see `letters_with_stdin_mode`), the modes
+    /// from `stdin_mode` are expected to be overridden, so don't count as conflicts
+    fn merged_mode_with_stdin(
+        &self,
+        letters: Option<&str>,
+        stdin_mode_prepended: bool,
+    ) -> Result<MergedMode> {
+        let mut modes = vec![];
+        self.resolve_mode_spec(letters.unwrap_or(""), &mut vec![], &mut modes)?;
+        let mut stdin_modes = vec![];
+        if let (Some(stdin_mode), true) = (&self.stdin_mode, stdin_mode_prepended) {
+            self.resolve_mode_spec(stdin_mode, &mut vec![], &mut stdin_modes)?;
         }
+        Ok(merge_modes(
+            &self.default_mode,
+            &modes,
+            &stdin_modes,
+            &self.merge,
+        ))
+    }
 
-        if let (None, Some(quickfix_cmd)) = (&self.quickfix_cmd, &other.quickfix_cmd) {
-            self.quickfix_cmd = Some(quickfix_cmd.clone());
+    /// Merges the modes in `letters` onto the default mode, warning about conflicts.
+    /// `stdin_mode_prepended` is whether `letters` come from `letters_with_stdin_mode` with
+    /// `stdin_mode` prepended.
+    pub fn get_merged_mode(
+        &self,
+        letters: Option<&str>,
+        stdin_mode_prepended: bool,
+    ) -> Result<Mode> {
+        let merged = self.merged_mode_with_stdin(letters, stdin_mode_prepended)?;
+        for conflict in &merged.conflicts {
+            eprintln!("Warning: {conflict} (see `[merge]` in the config to change this)");
         }
 
-        if let (None, Some(filter)) = (&self.filter, &other.filter) {
-            self.filter = Some(filter.clone());
+        debug!("Combined merged mode is: {:?}", &merged.mode);
+        Ok(merged.mode)
+    }
+
+    /// Whether `letters_with_stdin_mode` prepends `stdin_mode`
+    pub fn uses_stdin_mode(&self, stdin_is_piped: bool) -> bool {
+        self.stdin_mode.is_some() && stdin_is_piped
+    }
+
+    /// Returns the mode letters to use, prepending `stdin_mode` if stdin is a pipe or file. Letters
+    /// given on the command line come after, so a mode with its own script still takes precedence
+    /// (and that script sees stdin).
+    pub fn letters_with_stdin_mode(
+        &self,
+        letters: Option<&str>,
+        stdin_is_piped: bool,
+    ) -> Option<String> {
+        match &self.stdin_mode {
+            Some(stdin_mode) if self.uses_stdin_mode(stdin_is_piped) => {
+                debug!("stdin is piped, using stdin_mode {stdin_mode:?}");
+                Some(format!("{stdin_mode}{}", letters.unwrap_or("")))
+            }
+            _ => letters.map(str::to_owned),
         }
     }
-}
 
-impl AppConfig {
-    pub fn get_merged_mode(&self, letters: Option<&str>) -> Result<Mode> {
-        let default = std::iter::once(&self.default_mode);
-        let lets = letters
-            .unwrap_or("")
-            .chars()
-            .map(|c| self.get_mode(c))
-            .collect::<Result<Vec<&Mode>>>()?;
-        let mut combined = default.chain(lets).rev();
-        let mut aggregate_mode = combined
-            .next()
-            .ok_or_eyre("Internal error, no iterator for default mode")?
-            .to_owned();
-        for mode in combined {
-            aggregate_mode.reverse_merge(mode);
-        }
-
-        debug!("Combined merged mode is: {:?}", &aggregate_mode);
-        Ok(aggregate_mode)
-    }
-
-    pub fn get_mode(&self, mode_letter: char) -> Result<&Mode> {
+    pub fn get_mode(&self, name: &str) -> Result<&Mode> {
         self.modes
-            .get(&mode_letter)
-            .ok_or_else(|| eyre!("No mode found for {mode_letter}"))
+            .get(name)
+            .ok_or_else(|| eyre!("No mode found for {name}"))
+    }
+
+    /// Appends the modes in `spec` to `modes`, expanding aliases. `aliases_seen` is the chain of
+    /// aliases currently being expanded, used to detect cycles.
+    fn resolve_mode_spec<'a>(
+        &'a self,
+        spec: &'a str,
+        aliases_seen: &mut Vec<&'a str>,
+        modes: &mut Vec<&'a Mode>,
+    ) -> Result<()> {
+        for name in parse_mode_spec(spec)? {
+            if let Some(mode) = self.modes.get(name) {
+                modes.push(mode);
+            } else if let Some((name, alias_spec)) = self.aliases.get_key_value(name) {
+                if aliases_seen.contains(&name.as_str()) {
+                    return Err(eyre!(
+                        "Alias cycle: {} -> {name}",
+                        aliases_seen.join(" -> ")
+                    ));
+                }
+                aliases_seen.push(name);
+                self.resolve_mode_spec(alias_spec, aliases_seen, modes)?;
+                aliases_seen.pop();
+            } else {
+                return Err(eyre!("No mode or alias found for {name}"));
+            }
+        }
+        Ok(())
     }
 }
 
-fn is_entry_of_toml_file(entry: &DirEntry) -> bool {
+/// Splits a mode spec (the first argument) into mode/alias names. Each character is a one-letter
+/// name, except that `:` starts a long name which runs until the next `:` (e.g. `z:rails-routes`).
+pub fn parse_mode_spec(spec: &str) -> Result<Vec<&str>> {
+    let mut names = vec![];
+    let mut rest = spec;
+    while let Some(c) = rest.chars().next() {
+        if c == ':' {
+            let name_and_rest = &rest[1..];
+            let end = name_and_rest.find(':').unwrap_or(name_and_rest.len());
+            if end == 0 {
+                return Err(eyre!("Empty mode name after ':' in mode spec {spec:?}"));
+            }
+            names.push(&name_and_rest[..end]);
+            rest = &name_and_rest[end..];
+        } else {
+            names.push(&rest[..c.len_utf8()]);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Ok(names)
+}
+
+/// Describes where a mode was defined (the config file), for error messages
+fn mode_source(figment: &Figment, name: &str) -> String {
+    figment
+        .find_metadata(&format!("modes.{name}"))
+        .and_then(|metadata| metadata.source.as_ref())
+        .map_or_else(
+            || "unknown config file".to_owned(),
+            |source| source.to_string(),
+        )
+}
+
+pub fn extract_config(figment: &Figment) -> Result<AppConfig> {
+    let mut config: AppConfig = figment.extract()?;
+    config.merge.validate()?;
+    config.resolve_extends(figment)?;
+    for (name, mode) in &config.modes {
+        validate_arg_spec(name, mode.arg_spec.as_deref().unwrap_or_default())?;
+    }
+    for (name, location) in &config.locations {
+        location.validate(name)?;
+    }
+    Ok(config)
+}
+
+/// Extensions of config files, which can be TOML, JSON or YAML
+pub const CONFIG_EXTENSIONS: [&str; 4] = ["toml", "json", "yaml", "yml"];
+
+fn is_entry_of_config_file(entry: &DirEntry) -> bool {
     match entry.path().extension() {
-        Some(x) => x == "toml",
+        Some(x) => CONFIG_EXTENSIONS.iter().any(|ext| x == *ext),
         _ => false,
     }
 }
 
-pub fn get_config() -> Result<AppConfig> {
-    let mut figment = Figment::new();
-    let path = config_dir()?;
+/// Merges a config file into `figment` using the provider for its format (from its extension)
+pub fn merge_config_file(figment: Figment, file: &Path) -> Figment {
+    match file.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => figment.merge(Json::file(file)),
+        Some("yaml" | "yml") => figment.merge(Yaml::file(file)),
+        _ => figment.merge(Toml::file(file)),
+    }
+}
+
+/// A config file to merge. Trusted project configs come with the contents that were checked for
+/// trust, which are loaded instead of reading the file again (it may have changed since).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub contents: Option<String>,
+}
+
+impl From<PathBuf> for ConfigFile {
+    fn from(path: PathBuf) -> Self {
+        Self {
+            path,
+            contents: None,
+        }
+    }
+}
+
+impl ConfigFile {
+    fn merge_into(&self, figment: Figment) -> Figment {
+        match &self.contents {
+            Some(contents) => figment.merge(FileContents {
+                path: &self.path,
+                contents,
+            }),
+            None => merge_config_file(figment, &self.path),
+        }
+    }
+}
+
+/// Provides a config file's contents, parsed in the format for its extension, with the file as
+/// their source (like `Toml::file` etc., which would read the file)
+struct FileContents<'a> {
+    path: &'a Path,
+    contents: &'a str,
+}
+
+impl Provider for FileContents<'_> {
+    fn metadata(&self) -> Metadata {
+        let format = match self.path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Json::NAME,
+            Some("yaml" | "yml") => Yaml::NAME,
+            _ => Toml::NAME,
+        };
+        Metadata::from(format!("{format} file"), self.path)
+    }
 
-    let mut entries = std::fs::read_dir(path.clone())?
+    fn data(&self) -> Result<Map<Profile, Dict>, figment::Error> {
+        match self.path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Json::string(self.contents).data(),
+            Some("yaml" | "yml") => Yaml::string(self.contents).data(),
+            _ => Toml::string(self.contents).data(),
+        }
+    }
+}
+
+/// Config files in a config path: the path itself if it's a file, or the config files in it (sorted
+/// by name) if it's a directory. Missing directories have no config files.
+fn config_files_in(path: &Path) -> Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    if !path.is_dir() {
+        debug!("Config path {} does not exist", path.display());
+        return Ok(vec![]);
+    }
+
+    let mut entries = std::fs::read_dir(path)?
         .filter(|entry| match entry {
             Err(_) => true,
-            Ok(entry) => is_entry_of_toml_file(entry),
+            Ok(entry) => is_entry_of_config_file(entry),
         })
         .collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|a| a.path());
+    Ok(entries.iter().map(DirEntry::path).collect())
+}
+
+/// All config files, in the order they are merged: those in each config path, then trusted
+/// project configs, with `include`d files before the file including them
+pub fn config_files() -> Result<Vec<ConfigFile>> {
+    let paths = config_paths()?;
 
-    if entries.is_empty() {
-        return Err(eyre!("No config files found in {}, run with --init to install default config and scripts or --help for more info", path.display()));
+    let mut files = vec![];
+    for path in &paths {
+        files.extend(config_files_in(path)?);
+    }
+
+    if files.is_empty() {
+        let paths = paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        return Err(eyre!("No config files found in {paths}, run with --init to install default config and scripts or --help for more info"));
+    }
+
+    let mut files = resolve_includes(&files)?
+        .into_iter()
+        .map(ConfigFile::from)
+        .collect::<Vec<_>>();
+    // Project configs are layered on top of user config. Their includes are resolved (and checked
+    // for trust) by trusted_project_configs.
+    for file in trusted_project_configs()? {
+        if !files.iter().any(|existing| existing.path == file.path) {
+            files.push(file);
+        }
+    }
+    Ok(files)
+}
+
+pub fn config_figment(files: &[ConfigFile]) -> Figment {
+    let mut figment = Figment::new();
+    for file in files {
+        figment = file.merge_into(figment);
+    }
+    figment
+}
+
+pub fn get_config() -> Result<AppConfig> {
+    extract_config(&config_figment(&config_files()?))
+}
+
+/// Prints the config files in the order they are merged and where each mode is defined (for
+/// --config-sources)
+pub fn print_config_sources() -> Result<()> {
+    let files = config_files()?;
+    let figment = config_figment(&files);
+    println!("Config files (later files take precedence):");
+    for file in &files {
+        println!("  {}", file.path.display());
+    }
+
+    let config = extract_config(&figment)?;
+    println!();
+    println!("Modes:");
+    let mut names = config.modes.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        println!("  {name}: {}", mode_source(&figment, name));
+    }
+    for key in [
+        "default_mode",
+        "transforms",
+        "stdin_mode",
+        "aliases",
+        "locations",
+        "merge",
+    ] {
+        if let Some(source) = figment
+            .find_metadata(key)
+            .and_then(|metadata| metadata.source.as_ref())
+        {
+            println!("{key}: {source}");
+        }
+    }
+    Ok(())
+}
+
+/// A config for tests: `toml` merged onto a default mode (script `default-script`, cmd `nvim`) and
+/// modes `g` (script `git-script`), `z` (cmd `zellij`) and `rails-routes`
+#[cfg(test)]
+pub fn test_config(toml: &str) -> Result<AppConfig> {
+    let base = r#"
+        transforms = []
+        [default_mode]
+        name = "default"
+        desc = "default"
+        script = "default-script"
+        cmd = "nvim"
+        [modes.g]
+        name = "git"
+        desc = "git"
+        script = "git-script"
+        [modes.z]
+        name = "zellij"
+        desc = "zellij"
+        cmd = "zellij"
+        [modes.rails-routes]
+        name = "rails routes"
+        desc = "rails routes"
+        script = "rails-routes-script"
+    "#;
+    extract_config(&Figment::from(Toml::string(base)).merge(Toml::string(toml)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script_and_cmd(mode: &Mode) -> (String, String) {
+        let script = match &mode.script {
+            Some(Script::Command(cmd)) => cmd.clone(),
+            other => panic!("unexpected script {other:?}"),
+        };
+        (script, mode.cmd.clone().unwrap())
+    }
+
+    #[test]
+    fn test_parse_mode_spec() -> Result<()> {
+        assert_eq!(parse_mode_spec("")?, Vec::<&str>::new());
+        assert_eq!(parse_mode_spec("gz")?, vec!["g", "z"]);
+        assert_eq!(parse_mode_spec(":rails-routes")?, vec!["rails-routes"]);
+        assert_eq!(
+            parse_mode_spec("z:rails-routes:g")?,
+            vec!["z", "rails-routes", "g"]
+        );
+        for spec in ["g:", "g::z", ":"] {
+            assert_eq!(
+                parse_mode_spec(spec).unwrap_err().to_string(),
+                format!("Empty mode name after ':' in mode spec {spec:?}")
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_letters_with_stdin_mode() -> Result<()> {
+        let config = test_config(r#"stdin_mode = "-""#)?;
+        assert_eq!(
+            config.letters_with_stdin_mode(Some("g"), false),
+            Some("g".into())
+        );
+        assert_eq!(config.letters_with_stdin_mode(None, false), None);
+        assert_eq!(
+            config.letters_with_stdin_mode(Some("g"), true),
+            Some("-g".into())
+        );
+        assert_eq!(config.letters_with_stdin_mode(None, true), Some("-".into()));
+
+        let config = test_config("")?;
+        assert_eq!(
+            config.letters_with_stdin_mode(Some("g"), true),
+            Some("g".into())
+        );
+        assert_eq!(config.letters_with_stdin_mode(None, true), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_names_and_aliases() -> Result<()> {
+        let config = test_config(
+            r#"
+            [aliases]
+            rz = "z:rails-routes"
+            review = ":rz"
+            "#,
+        )?;
+        let mode = config.get_merged_mode(Some("gz"), false)?;
+        assert_eq!(
+            script_and_cmd(&mode),
+            ("git-script".into(), "zellij".into())
+        );
+        let mode = config.get_merged_mode(Some(":review"), false)?;
+        assert_eq!(
+            script_and_cmd(&mode),
+            ("rails-routes-script".into(), "zellij".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_alias_cycle() -> Result<()> {
+        let config = test_config(
+            r#"
+            [aliases]
+            a = ":b"
+            b = ":a"
+            "#,
+        )?;
+        let err = config.get_merged_mode(Some(":a"), false).unwrap_err();
+        assert_eq!(err.to_string(), "Alias cycle: a -> b -> a");
+        Ok(())
+    }
+
+    const MERGE_MODES: &str = r#"
+        [default_mode]
+        quickfix_cmd = "nvim -q"
+        env = { A = "default", B = "default" }
+        [modes.q]
+        name = "quickfix"
+        desc = "quickfix"
+        script = "qf-script"
+        quickfix = true
+        script_uses_tempfile = true
+        timeout = 5
+        filter = "qf-filter"
+        filter_timeout = 3
+        env = { B = "q", C = "q" }
+        args = ["--q"]
+        [modes.f]
+        name = "filter"
+        desc = "filter"
+        filter = "f-filter"
+        args = ["--f"]
+        [modes.y]
+        name = "other zellij"
+        desc = "other zellij"
+        cmd = "zellij-other"
+        env = { C = "y" }
+    "#;
+
+    fn script_name(script: &Script) -> String {
+        match script {
+            Script::Command(cmd) => cmd.clone(),
+            other => panic!("unexpected script {other:?}"),
+        }
+    }
+
+    /// A field of a merged mode as a string (`env.<key>` for an env var), or `-` if unset
+    fn field(mode: &Mode, name: &str) -> String {
+        fn or_unset<T: ToString>(value: Option<T>) -> String {
+            value.map_or_else(|| "-".to_owned(), |value| value.to_string())
+        }
+        match name {
+            "name" => mode.name.clone(),
+            "desc" => mode.desc.clone(),
+            "script" => or_unset(mode.script.as_ref().map(script_name)),
+            "filter" => or_unset(mode.filter.as_ref().map(script_name)),
+            "cmd" => or_unset(mode.cmd.as_ref()),
+            "quickfix_cmd" => or_unset(mode.quickfix_cmd.as_ref()),
+            "args" => or_unset(mode.args.as_ref().map(|args| args.join(" "))),
+            "quickfix" => or_unset(mode.quickfix),
+            "script_uses_tempfile" => or_unset(mode.script_uses_tempfile),
+            "timeout" => or_unset(mode.timeout),
+            "filter_timeout" => or_unset(mode.filter_timeout),
+            "prior_filters" => mode
+                .prior_filters
+                .iter()
+                .map(|prior| {
+                    format!(
+                        "{} ({})",
+                        script_name(&prior.script),
+                        or_unset(prior.timeout)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            _ => match name.strip_prefix("env.") {
+                Some(key) => or_unset(mode.env.as_ref().and_then(|env| env.get(key))),
+                None => panic!("unknown field {name}"),
+            },
+        }
+    }
+
+    /// [merge] settings, mode letters, and the expected fields of the merged mode
+    type MergeCase<'a> = (&'a str, &'a str, &'a [(&'a str, &'a str)]);
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let first_wins = "cmd = 'first-wins'\nscript = 'first-wins'";
+        let append = "filter = 'append'\nargs = 'append'";
+        let cases: &[MergeCase] = &[
+            // The default mode fills unset fields
+            (
+                "",
+                "",
+                &[
+                    ("name", "default"),
+                    ("script", "default-script"),
+                    ("cmd", "nvim"),
+                ],
+            ),
+            (
+                "",
+                "z",
+                &[
+                    ("script", "default-script"),
+                    ("cmd", "zellij"),
+                    ("quickfix_cmd", "nvim -q"),
+                ],
+            ),
+            // Later letters override
+            ("", "zy", &[("cmd", "zellij-other")]),
+            ("", "yz", &[("cmd", "zellij")]),
+            ("", "qf", &[("filter", "f-filter"), ("args", "--f")]),
+            // The name and desc are the last mode's
+            ("", "gz", &[("name", "zellij"), ("desc", "zellij")]),
+            // Script options and args travel with the script
+            (
+                "",
+                "q",
+                &[
+                    ("quickfix", "true"),
+                    ("script_uses_tempfile", "true"),
+                    ("timeout", "5"),
+                ],
+            ),
+            (
+                "",
+                "qg",
+                &[
+                    ("script", "git-script"),
+                    ("quickfix", "-"),
+                    ("script_uses_tempfile", "-"),
+                    ("timeout", "-"),
+                    ("args", "-"),
+                ],
+            ),
+            ("", "gq", &[("args", "--q")]),
+            // ...unless args are appended
+            ("args = 'append'", "qg", &[("args", "--q")]),
+            // The filter timeout travels with the filter
+            ("", "q", &[("filter_timeout", "3")]),
+            ("", "qf", &[("filter_timeout", "-"), ("prior_filters", "")]),
+            // env is merged by key
+            (
+                "",
+                "qy",
+                &[("env.A", "default"), ("env.B", "q"), ("env.C", "y")],
+            ),
+            // first-wins keeps the first mode's value...
+            (first_wins, "zy", &[("cmd", "zellij")]),
+            (
+                first_wins,
+                "gq",
+                &[("script", "git-script"), ("quickfix", "-")],
+            ),
+            // ...with the default mode only a fallback
+            (first_wins, "y", &[("cmd", "zellij-other")]),
+            // append keeps earlier filters to run first
+            (
+                append,
+                "qf",
+                &[
+                    ("args", "--q --f"),
+                    ("prior_filters", "qf-filter (3)"),
+                    ("filter", "f-filter"),
+                ],
+            ),
+        ];
+        for (merge, letters, expected) in cases {
+            let config = test_config(&format!("{MERGE_MODES}\n[merge]\n{merge}"))?;
+            let letters = Some(*letters).filter(|letters| !letters.is_empty());
+            let mode = config.get_merged_mode(letters, false)?;
+            for (name, value) in *expected {
+                assert_eq!(
+                    field(&mode, name),
+                    *value,
+                    "{name} of mode {letters:?} with [merge] {merge:?}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_append_only_for_some_fields() {
+        let err = test_config("[merge]\ncmd = 'append'").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "merge.cmd cannot be \"append\" (only filter, args and env can)"
+        );
+    }
+
+    #[test]
+    fn test_merge_script_conflicts() -> Result<()> {
+        let config = test_config(&format!("stdin_mode = \"q\"\n{MERGE_MODES}"))?;
+        let modes = |names: &[&str]| -> Result<Vec<&Mode>> {
+            names.iter().map(|name| config.get_mode(name)).collect()
+        };
+        let merged = merge_modes(
+            &config.default_mode,
+            &modes(&["g", "z", "rails-routes"])?,
+            &[],
+            &config.merge,
+        );
+        assert_eq!(
+            merged.conflicts,
+            vec!["modes \"git\" and \"rails routes\" both set a script; using \"rails routes\"'s"]
+        );
+        // modes from stdin_mode are meant to be overridden
+        let merged = merge_modes(
+            &config.default_mode,
+            &modes(&["q", "g"])?,
+            &modes(&["q"])?,
+            &config.merge,
+        );
+        assert!(merged.conflicts.is_empty());
+        // ...but only when stdin_mode was actually prepended
+        assert!(config
+            .merged_mode_with_stdin(Some("qg"), true)?
+            .conflicts
+            .is_empty());
+        assert_eq!(
+            config
+                .merged_mode_with_stdin(Some("qg"), false)?
+                .conflicts
+                .len(),
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_and_yaml_config_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files = [
+            (
+                "000.toml",
+                "transforms = []\n[default_mode]\nname = 'd'\ndesc = 'd'\ncmd = 'nvim'",
+            ),
+            (
+                "100.json",
+                r#"{"modes": {"j": {"name": "json", "desc": "json", "script": ["a", "b"]}}}"#,
+            ),
+            (
+                "200.yaml",
+                "modes:\n  y:\n    name: yaml\n    desc: yaml\n    script: yaml-script\n",
+            ),
+        ]
+        .map(|(name, contents)| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).map(|_| ConfigFile::from(path))
+        });
+        let files = files.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        let config = extract_config(&config_figment(&files))?;
+        let mode = config.get_merged_mode(Some("y"), false)?;
+        assert_eq!(script_and_cmd(&mode), ("yaml-script".into(), "nvim".into()));
+        assert!(matches!(
+            &config.get_mode("j")?.script,
+            Some(Script::CommandWithArgs(args)) if args == &["a", "b"]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extends() -> Result<()> {
+        let config = test_config(
+            r#"
+            [modes.c]
+            name = "cached"
+            desc = "like git, but cached"
+            extends = "g"
+            args = ["--cached"]
+            [modes.C]
+            name = "cached zellij"
+            desc = "like cached, but in zellij"
+            extends = "c"
+            cmd = "zellij"
+            "#,
+        )?;
+        let mode = config.get_merged_mode(Some("C"), false)?;
+        assert_eq!(
+            script_and_cmd(&mode),
+            ("git-script".into(), "zellij".into())
+        );
+        assert_eq!(mode.args, Some(vec!["--cached".to_owned()]));
+        assert_eq!(mode.extends, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extends_uses_merge_config() -> Result<()> {
+        let config = test_config(
+            r#"
+            [merge]
+            args = "append"
+            script = "first-wins"
+            [modes.b]
+            name = "base"
+            desc = "base"
+            script = "base-script"
+            args = ["--base"]
+            [modes.c]
+            name = "child"
+            desc = "child"
+            extends = "b"
+            script = "child-script"
+            args = ["--child"]
+            "#,
+        )?;
+        let mode = config.get_mode("c")?;
+        // The mode extended is only a fallback, even with first-wins
+        assert!(matches!(&mode.script, Some(Script::Command(script)) if script == "child-script"));
+        assert_eq!(
+            mode.args,
+            Some(vec!["--base".to_owned(), "--child".to_owned()])
+        );
+        Ok(())
     }
-    entries.sort_by_key(|a| a.path());
 
-    for entry in entries {
-        figment = figment.merge(Toml::file(entry.path()));
+    #[test]
+    fn test_extends_cycle() {
+        let err = test_config(
+            r#"
+            [modes.a]
+            name = "a"
+            desc = "a"
+            extends = "b"
+            [modes.b]
+            name = "b"
+            desc = "b"
+            extends = "a"
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.starts_with("Cycle in mode `extends`: "), "{err}");
     }
-    Ok(figment.extract()?)
-    // .filter_map(|entry| entry?.path()).filter(|path| path.extension()? == "toml");
-    // let config: AppConfig = Figment::new().merge(Toml::file(path.clone())).extract()?;
-    // let mut tmp = Figment::new();
-    // tmp = tmp.merge(Toml::file(path.clone()));
-    // tmp = tmp.merge(Toml::file(path.clone()));
-    // let cfg = tmp.extract::<AppConfig>()?;
 }
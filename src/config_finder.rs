@@ -0,0 +1,322 @@
+use crate::path_util;
+use crate::transforms_replacement_preprocessor::expand_placeholders;
+use color_eyre::eyre::{eyre, Result};
+use ignore::WalkBuilder;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Tried after each standard path, e.g. ~/.config/foo.toml
+const EXTENSIONS: [&str; 5] = ["", ".json", ".yml", ".yaml", ".toml"];
+
+/// Finds config files for a program by name in the usual places
+#[derive(Debug)]
+struct ConfigLocator {
+    home: PathBuf,
+    /// $XDG_CONFIG_HOME, then $XDG_CONFIG_DIRS and any extra roots
+    config_dirs: Vec<PathBuf>,
+}
+
+impl ConfigLocator {
+    /// Places a config for `name` may be, most likely first
+    fn standard_paths(&self, name: &str) -> Vec<PathBuf> {
+        let home = &self.home;
+        let in_config_dir = |dir: &Path| {
+            [
+                dir.join(name).join("config"),
+                dir.join(name).join(name),
+                dir.join(name),
+            ]
+        };
+        let (config_home, other_config_dirs) = self
+            .config_dirs
+            .split_first()
+            .map_or((None, &[][..]), |(first, rest)| (Some(first), rest));
+
+        let mut paths = vec![home.join(format!(".{name}"))];
+        paths.extend(config_home.into_iter().flat_map(|dir| in_config_dir(dir)));
+        paths.extend([
+            home.join(format!(".{name}")).join("config"),
+            home.join(format!(".{name}rc")),
+            home.join(format!(".{name}config")),
+        ]);
+        paths.extend(other_config_dirs.iter().flat_map(|dir| in_config_dir(dir)));
+        paths
+    }
+
+    /// The first existing standard path (with one of `EXTENSIONS`, then `sub_path` appended),
+    /// preferring files to directories
+    fn find_first(&self, name: &str, sub_path: Option<&str>) -> Option<PathBuf> {
+        let candidates = self
+            .standard_paths(name)
+            .into_iter()
+            .flat_map(|path| {
+                EXTENSIONS.map(|ext| {
+                    let mut path = path.clone().into_os_string();
+                    path.push(ext);
+                    let path = PathBuf::from(path);
+                    match sub_path {
+                        Some(sub_path) => path.join(sub_path),
+                        None => path,
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let first = |is_kind: fn(&Path) -> bool| candidates.iter().find(|path| is_kind(path));
+        first(Path::is_file)
+            .or_else(|| first(Path::is_dir))
+            .cloned()
+    }
+
+    /// Config files and dirs in the home and config dirs (e.g. ~/.vimrc, ~/.config/fish,
+    /// ~/.config/git/config) with paths containing `filter`
+    fn all_configs(&self, filter: &str) -> Vec<PathBuf> {
+        let children = |dir: &Path| -> Vec<PathBuf> {
+            let Ok(entries) = fs::read_dir(dir) else {
+                return vec![];
+            };
+            let mut children = entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .collect::<Vec<_>>();
+            children.sort();
+            children
+        };
+        let dotfiles = children(&self.home).into_iter().filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+        });
+        let dotfiles = dotfiles.map(|path| (path, self.home.as_path()));
+        let in_config_dirs = self.config_dirs.iter().flat_map(|dir| {
+            children(dir)
+                .into_iter()
+                .map(move |path| (path, dir.as_path()))
+        });
+
+        let mut configs = vec![];
+        for (path, parent) in dotfiles.chain(in_config_dirs) {
+            let config = path.join("config");
+            let candidates = [Some(path), config.is_file().then_some(config)];
+            for path in candidates.into_iter().flatten() {
+                // Match against e.g. `.config/git/config` rather than the whole path
+                let relative = path.strip_prefix(parent).unwrap_or(&path);
+                if relative.to_string_lossy().contains(filter) && !configs.contains(&path) {
+                    configs.push(path);
+                }
+            }
+        }
+        configs
+    }
+
+    /// Files in the standard paths for `name` with names containing `filter` (ignoring case)
+    fn files_inside(&self, name: &str, filter: &str) -> Vec<PathBuf> {
+        let filter = filter.to_lowercase();
+        let mut files: Vec<PathBuf> = vec![];
+        for path in self.standard_paths(name) {
+            if !path.exists() {
+                continue;
+            }
+            let walk = WalkBuilder::new(&path)
+                .standard_filters(false)
+                .sort_by_file_name(|a, b| a.cmp(b))
+                .build();
+            for entry in walk.filter_map(|entry| entry.ok()) {
+                let is_match = entry.path().is_file()
+                    && entry
+                        .file_name()
+                        .to_string_lossy()
+                        .to_lowercase()
+                        .contains(&filter);
+                if is_match && !files.iter().any(|file| file == entry.path()) {
+                    files.push(entry.into_path());
+                }
+            }
+        }
+        files
+    }
+
+    /// Configs for `v c [<name> [<sub-path>]]`:
+    /// * no name (or `-`): all configs, for choosing with the mode's filter
+    /// * `<name>`: the first standard path for it, or else all configs containing `<name>`
+    /// * `<name> <sub-path>`: `<sub-path>` in the first standard path containing it, or else files
+    ///   inside the standard paths with names containing `<sub-path>` (all files for `-`), or else
+    ///   a new file `<sub-path>` in the first existing standard directory
+    fn locate(&self, name: Option<&str>, sub_path: Option<&str>) -> Result<Vec<PathBuf>> {
+        let name = name.filter(|name| *name != "-");
+        let Some(name) = name else {
+            return Ok(self.all_configs(sub_path.unwrap_or("")));
+        };
+        let list_inside = sub_path == Some("-");
+        let sub_path = sub_path.filter(|_| !list_inside);
+        if !list_inside {
+            if let Some(first) = self.find_first(name, sub_path) {
+                return Ok(vec![first]);
+            }
+        }
+
+        match sub_path {
+            None if list_inside => Ok(self.files_inside(name, "")),
+            None => Ok(self.all_configs(name)),
+            Some(sub_path) => {
+                let files = self.files_inside(name, sub_path);
+                if !files.is_empty() {
+                    return Ok(files);
+                }
+                self.standard_paths(name)
+                    .into_iter()
+                    .find(|path| path.is_dir())
+                    .map(|dir| vec![dir.join(sub_path)])
+                    .ok_or_else(|| eyre!("No config found for {name}"))
+            }
+        }
+    }
+}
+
+/// Expands `~/` and placeholders such as ${home} in an extra root
+fn expand_root(root: &str) -> Result<PathBuf> {
+    let root = expand_placeholders(root);
+    Ok(match root.strip_prefix("~/") {
+        Some(rest) => path_util::home_dir()?.join(rest),
+        None => PathBuf::from(root),
+    })
+}
+
+/// `builtin:config [--root <dir>]... [<name>|- [<sub-path>|-]]`: prints where the config for
+/// `name` is (e.g. ~/.vimrc, ~/.config/git/config), searching ~/.<name>, ~/.<name>rc,
+/// ~/.config/<name>/, $XDG_CONFIG_DIRS and the extra roots, each with `.json`, `.yml`, `.yaml` or
+/// `.toml` too. When there's no single answer, prints the candidates for the mode's filter to
+/// choose from.
+pub fn config_source(args: &[String]) -> Result<()> {
+    let mut roots = vec![];
+    let mut positional = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--root" => {
+                let root = args
+                    .next()
+                    .ok_or_else(|| eyre!("--root requires a directory"))?;
+                roots.push(expand_root(root)?);
+            }
+            flag if flag.starts_with("--") => {
+                return Err(eyre!(
+                    "Unknown option {flag} for builtin:config (expected --root)"
+                ))
+            }
+            _ => positional.push(arg.as_str()),
+        }
+    }
+    let (name, sub_path) = match positional[..] {
+        [] => (None, None),
+        [name] => (Some(name), None),
+        [name, sub_path] => (Some(name), Some(sub_path)),
+        _ => return Err(eyre!("builtin:config takes a name and a sub-path at most")),
+    };
+
+    let mut config_dirs = vec![path_util::xdg_config_home()?];
+    config_dirs.extend(path_util::xdg_config_dirs());
+    config_dirs.extend(roots);
+    let locator = ConfigLocator {
+        home: path_util::home_dir()?,
+        config_dirs,
+    };
+    for path in locator.locate(name, sub_path)? {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> Result<(tempfile::TempDir, ConfigLocator)> {
+        let dir = tempfile::tempdir()?;
+        let home = dir.path().join("home");
+        let config_home = home.join(".config");
+        for file in [
+            ".vimrc",
+            ".gitconfig",
+            ".config/fish/config.fish",
+            ".config/fish/functions/v.fish",
+            ".config/git/config",
+            ".config/starship.toml",
+        ] {
+            fs::create_dir_all(home.join(file).parent().unwrap())?;
+            fs::write(home.join(file), "")?;
+        }
+        fs::create_dir_all(config_home.join("empty"))?;
+        let extra_root = dir.path().join("dotfiles");
+        fs::create_dir_all(extra_root.join("tool"))?;
+        fs::write(extra_root.join("tool/config"), "")?;
+        let locator = ConfigLocator {
+            home,
+            config_dirs: vec![config_home, extra_root],
+        };
+        Ok((dir, locator))
+    }
+
+    #[test]
+    fn test_locate_by_name() -> Result<()> {
+        let (_dir, locator) = setup()?;
+        let home = locator.home.clone();
+        let locate = |name, sub_path| locator.locate(Some(name), sub_path).unwrap();
+        assert_eq!(locate("git", None), vec![home.join(".config/git/config")]);
+        assert_eq!(locate("vim", None), vec![home.join(".vimrc")]);
+        assert_eq!(
+            locate("starship", None),
+            vec![home.join(".config/starship.toml")]
+        );
+        assert_eq!(locate("fish", None), vec![home.join(".config/fish")]);
+        assert_eq!(
+            locate("tool", None),
+            vec![locator.config_dirs[1].join("tool/config")]
+        );
+        // Not found: all configs containing the name
+        assert_eq!(locate("gitc", None), vec![home.join(".gitconfig")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_locate_sub_path() -> Result<()> {
+        let (_dir, locator) = setup()?;
+        let home = locator.home.clone();
+        let locate = |name, sub_path| locator.locate(Some(name), Some(sub_path)).unwrap();
+        assert_eq!(
+            locate("fish", "functions"),
+            vec![home.join(".config/fish/functions")]
+        );
+        // Files inside with matching names
+        assert_eq!(
+            locate("fish", "V."),
+            vec![home.join(".config/fish/functions/v.fish")]
+        );
+        assert_eq!(
+            locate("fish", "-"),
+            vec![
+                home.join(".config/fish/config.fish"),
+                home.join(".config/fish/functions/v.fish")
+            ]
+        );
+        // A new file in the config dir
+        assert_eq!(
+            locate("empty", "new.toml"),
+            vec![home.join(".config/empty/new.toml")]
+        );
+        assert!(locator.locate(Some("nothing"), Some("x")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_configs() -> Result<()> {
+        let (_dir, locator) = setup()?;
+        let home = locator.home.clone();
+        assert_eq!(
+            locator.locate(Some("-"), Some("git"))?,
+            vec![
+                home.join(".gitconfig"),
+                home.join(".config/git"),
+                home.join(".config/git/config")
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use crate::config::merge_config_file;
+use crate::path_util::home_dir;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use figment::Figment;
+use log::debug;
+use std::path::{Path, PathBuf};
+
+/// Expands the `include` directives in `files`, returning all config files in the order they
+/// should be merged. Included files come just before the file including them (so the including
+/// file takes precedence); a file included more than once is only loaded the first time.
+pub fn resolve_includes(files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut resolved = vec![];
+    for file in files {
+        add_with_includes(file, &mut vec![], &mut resolved)?;
+    }
+    Ok(resolved)
+}
+
+fn add_with_includes(
+    file: &Path,
+    chain: &mut Vec<PathBuf>,
+    resolved: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let canonical = file
+        .canonicalize()
+        .wrap_err_with(|| format!("Failed to find config file {}", file.display()))?;
+    if chain.contains(&canonical) {
+        let chain = chain
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+        return Err(eyre!("Config include cycle: {}", chain.join(" -> ")));
+    }
+    if resolved.contains(&canonical) {
+        debug!("Config file {} already loaded", file.display());
+        return Ok(());
+    }
+
+    chain.push(canonical.clone());
+    for pattern in includes_in(&canonical)? {
+        for included in expand_include(&pattern, &canonical)? {
+            debug!("{} includes {}", canonical.display(), included.display());
+            add_with_includes(&included, chain, resolved)?;
+        }
+    }
+    chain.pop();
+
+    resolved.push(canonical);
+    Ok(())
+}
+
+/// The `include` list in a config file, if any
+fn includes_in(file: &Path) -> Result<Vec<String>> {
+    let figment = merge_config_file(Figment::new(), file);
+    if figment.find_value("include").is_err() {
+        return Ok(vec![]);
+    }
+    figment
+        .extract_inner("include")
+        .wrap_err_with(|| format!("Invalid `include` in {}", file.display()))
+}
+
+/// Resolves an include pattern (relative to the including file's directory, `~/` meaning the home
+/// directory) to the files it matches, sorted. A pattern without glob characters must exist.
+fn expand_include(pattern: &str, including_file: &Path) -> Result<Vec<PathBuf>> {
+    let path = match pattern.strip_prefix("~/") {
+        Some(rest) => home_dir()?.join(rest),
+        None => including_file
+            .parent()
+            .map_or_else(|| PathBuf::from(pattern), |dir| dir.join(pattern)),
+    };
+
+    if !pattern.contains(['*', '?', '[']) {
+        if !path.is_file() {
+            return Err(eyre!(
+                "Config file {} not found (included from {})",
+                path.display(),
+                including_file.display()
+            ));
+        }
+        return Ok(vec![path]);
+    }
+
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| eyre!("Invalid unicode in include {}", path.display()))?;
+    let mut matches = glob::glob(path_str)
+        .wrap_err_with(|| {
+            format!(
+                "Invalid include pattern {pattern} in {}",
+                including_file.display()
+            )
+        })?
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    matches.sort();
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_resolve_includes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path().canonicalize()?;
+        fs::create_dir_all(dir.join("team"))?;
+        fs::write(dir.join("team/b.yaml"), "include: [c.json]")?;
+        fs::write(dir.join("team/c.json"), "{}")?;
+        fs::write(dir.join("team/a.toml"), "include = ['../base.toml']")?;
+        fs::write(dir.join("base.toml"), "")?;
+        fs::write(dir.join("main.toml"), "include = ['team/*', 'base.toml']")?;
+
+        assert_eq!(
+            resolve_includes(&[dir.join("main.toml")])?,
+            vec![
+                dir.join("base.toml"),
+                dir.join("team/a.toml"),
+                dir.join("team/c.json"),
+                dir.join("team/b.yaml"),
+                dir.join("main.toml"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_cycle() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.toml"), "include = ['b.toml']")?;
+        fs::write(dir.path().join("b.toml"), "include = ['a.toml']")?;
+        let err = resolve_includes(&[dir.path().join("a.toml")]).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Config include cycle: "),
+            "{err}"
+        );
+        Ok(())
+    }
+}
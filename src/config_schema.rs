@@ -0,0 +1,205 @@
+use crate::config::{config_figment, config_files, extract_config, AppConfig};
+use color_eyre::eyre::{eyre, Result};
+use figment::Figment;
+use jsonschema::error::ValidationErrorKind;
+use jsonschema::paths::Location;
+use serde_json::Value;
+
+/// JSON Schema for the config files, generated from `AppConfig`
+pub fn schema() -> Value {
+    schemars::schema_for!(AppConfig).to_value()
+}
+
+/// Prints the JSON Schema (for --print-schema)
+pub fn print_schema() -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&schema())?);
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SchemaError {
+    /// Dotted path to the invalid value, e.g. "modes.g.filter"
+    pub path: String,
+    pub message: String,
+}
+
+/// Problems found by validating a config value against the schema
+#[derive(Debug, Default, PartialEq)]
+pub struct SchemaProblems {
+    pub errors: Vec<SchemaError>,
+    /// Unknown keys, which are ignored when loading (so configs written by older versions, e.g.
+    /// with a `[scripts]` table, still load) but are likely typos
+    pub unknown_keys: Vec<String>,
+}
+
+fn dotted_path(location: &Location) -> String {
+    location
+        .iter()
+        .map(|segment| segment.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Validates a config value against the schema, returning every problem found (sorted by path)
+pub fn validate(schema: &Value, value: &Value) -> Result<SchemaProblems> {
+    let validator =
+        jsonschema::validator_for(schema).map_err(|err| eyre!("Invalid config schema: {err}"))?;
+    let mut problems = SchemaProblems::default();
+    for error in validator.iter_errors(value) {
+        let path = dotted_path(error.instance_path());
+        match error.kind() {
+            ValidationErrorKind::AdditionalProperties { unexpected } => {
+                problems.unknown_keys.extend(unexpected.iter().map(|key| {
+                    if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{path}.{key}")
+                    }
+                }))
+            }
+            _ => problems.errors.push(SchemaError {
+                path,
+                message: error.to_string(),
+            }),
+        }
+    }
+    problems.errors.sort_by(|a, b| a.path.cmp(&b.path));
+    problems.unknown_keys.sort();
+    Ok(problems)
+}
+
+/// The config file where the value at `path` (or its closest parent) was defined
+fn source_of(figment: &Figment, path: &str) -> String {
+    let mut path = path;
+    loop {
+        if let Some(source) = figment
+            .find_metadata(path)
+            .and_then(|metadata| metadata.source.as_ref())
+        {
+            return source.to_string();
+        }
+        match path.rfind('.') {
+            Some(i) => path = &path[..i],
+            None => return "unknown config file".to_owned(),
+        }
+    }
+}
+
+/// Validates the merged config against the schema and then loads it, reporting every problem found
+/// (for --check-config)
+pub fn check_config() -> Result<()> {
+    let files = config_files()?;
+    let figment = config_figment(&files);
+    let value: Value = figment.extract()?;
+
+    let problems = validate(&schema(), &value)?;
+    for key in &problems.unknown_keys {
+        eprintln!(
+            "Warning: {key}: unknown key, ignored (in {})",
+            source_of(&figment, key)
+        );
+    }
+    for error in &problems.errors {
+        let path = if error.path.is_empty() {
+            "(top level)"
+        } else {
+            &error.path
+        };
+        eprintln!(
+            "{path}: {} (in {})",
+            error.message,
+            source_of(&figment, &error.path)
+        );
+    }
+    if !problems.errors.is_empty() {
+        return Err(eyre!("Config has {} error(s)", problems.errors.len()));
+    }
+
+    extract_config(&figment)?;
+    println!("Config OK ({} file(s) checked)", files.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use figment::providers::{Format, Toml};
+    use serde_json::json;
+
+    fn errors(config: &Value) -> Vec<String> {
+        validate(&schema(), config)
+            .unwrap()
+            .errors
+            .into_iter()
+            .map(|error| format!("{}: {}", error.path, error.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_valid_config() {
+        let config = json!({
+            "transforms": [["^a/", ""]],
+            "default_mode": {"name": "d", "desc": "d", "script": "foo", "cmd": "nvim"},
+            "modes": {
+                "g": {"name": "g", "desc": "g", "script": ["git", "ls-files"], "timeout": 5},
+            },
+            "merge": {"filter": "append"},
+        });
+        assert_eq!(
+            validate(&schema(), &config).unwrap(),
+            SchemaProblems::default()
+        );
+    }
+
+    #[test]
+    fn test_validate_reports_all_errors() {
+        let config = json!({
+            "transforms": [["^a/", "", "extra"]],
+            "default_mode": {"name": "d"},
+            "modes": {
+                "g": {"name": "g", "desc": "g", "script": 5, "timeout": "5"},
+            },
+            "merge": {"filter": "sometimes"},
+        });
+        assert_eq!(
+            errors(&config),
+            vec![
+                r#"default_mode: "desc" is a required property"#,
+                r#"merge.filter: "sometimes" is not valid under any of the schemas listed in the 'oneOf' keyword"#,
+                "modes.g.script: 5 is not valid under any of the schemas listed in the 'anyOf' keyword",
+                r#"modes.g.timeout: "5" is not of types "integer", "null""#,
+                r#"transforms.0: ["^a/","","extra"] has more than 2 items"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_unknown_keys() {
+        let config = json!({
+            "transforms": [],
+            "default_mode": {"name": "d", "desc": "d"},
+            "modes": {"g": {"name": "g", "desc": "g", "scirpt": "git ls-files"}},
+            "merge": {"filters": "append"},
+            "scripts": {},
+        });
+        let problems = validate(&schema(), &config).unwrap();
+        assert_eq!(problems.errors, vec![]);
+        assert_eq!(
+            problems.unknown_keys,
+            vec!["merge.filters", "modes.g.scirpt", "scripts"]
+        );
+    }
+
+    #[test]
+    fn test_validate_baseline_example_config() -> Result<()> {
+        // As installed by --init before the schema existed, with a `[scripts]` table
+        let figment = Figment::from(Toml::string(include_str!(
+            "../testdata/baseline-example.toml"
+        )));
+        let problems = validate(&schema(), &figment.extract()?)?;
+        assert_eq!(problems.errors, vec![]);
+        assert_eq!(problems.unknown_keys, vec!["scripts"]);
+        extract_config(&figment)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+use crate::file_walker::print_as_found;
+use crate::quickfix::QuickfixEntry;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::{WalkBuilder, WalkState};
+use regex::bytes::{Regex, RegexBuilder};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+
+/// How much of a file is checked for a NUL byte to decide it's binary, as grep does
+const BINARY_CHECK_LENGTH: usize = 8192;
+
+/// Options for `builtin:grep`, mostly as in rg
+#[derive(Debug)]
+struct GrepArgs {
+    regex: Regex,
+    paths: Vec<PathBuf>,
+    hidden: bool,
+    no_ignore: bool,
+    /// Search binary files too
+    text: bool,
+    globs: Override,
+}
+
+impl GrepArgs {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut hidden = false;
+        let mut no_ignore = false;
+        let mut text = false;
+        let mut fixed_strings = false;
+        let mut word = false;
+        let mut case_sensitive = None;
+        let mut globs = OverrideBuilder::new(".");
+        let mut positional = vec![];
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-H" | "--hidden" => hidden = true,
+                "-I" | "--no-ignore" => no_ignore = true,
+                "-a" | "--text" => text = true,
+                "-F" | "--fixed-strings" => fixed_strings = true,
+                "-w" | "--word-regexp" => word = true,
+                "-s" | "--case-sensitive" => case_sensitive = Some(true),
+                "-i" | "--ignore-case" => case_sensitive = Some(false),
+                "-g" | "--glob" => {
+                    let glob = args.next().ok_or_else(|| eyre!("{arg} requires a glob"))?;
+                    globs
+                        .add(glob)
+                        .wrap_err_with(|| format!("Invalid glob {glob}"))?;
+                }
+                "--" => positional.extend(args.by_ref().map(String::as_str)),
+                flag if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(eyre!("Unknown option {flag} for builtin:grep"))
+                }
+                _ => positional.push(arg.as_str()),
+            }
+        }
+
+        let Some((pattern, paths)) = positional.split_first() else {
+            return Err(eyre!("Usage: builtin:grep [options] <pattern> [<path>...]"));
+        };
+        // Smart case, as in ag: case sensitive only if the pattern has an uppercase letter
+        let case_sensitive =
+            case_sensitive.unwrap_or_else(|| pattern.chars().any(char::is_uppercase));
+        let mut regex = if fixed_strings {
+            regex::escape(pattern)
+        } else {
+            pattern.to_string()
+        };
+        if word {
+            regex = format!(r"\b(?:{regex})\b");
+        }
+        let regex = RegexBuilder::new(&regex)
+            .case_insensitive(!case_sensitive)
+            .build()
+            .wrap_err_with(|| format!("Invalid pattern {pattern}"))?;
+        let paths = match paths {
+            [] => vec![PathBuf::from(".")],
+            paths => paths.iter().map(PathBuf::from).collect(),
+        };
+
+        Ok(Self {
+            regex,
+            paths,
+            hidden,
+            no_ignore,
+            text,
+            globs: globs.build()?,
+        })
+    }
+}
+
+/// Locations of the lines in `contents` matching `regex`, at the column of the first match.
+/// Binary contents (with a NUL byte near the start) have none, unless `text` is set.
+fn search(path: &Path, contents: &[u8], regex: &Regex, text: bool) -> Vec<QuickfixEntry> {
+    let start = &contents[..contents.len().min(BINARY_CHECK_LENGTH)];
+    if contents.is_empty() || (!text && start.contains(&0)) {
+        return vec![];
+    }
+    let contents = contents.strip_suffix(b"\n").unwrap_or(contents);
+    contents
+        .split(|byte| *byte == b'\n')
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let found = regex.find(line)?;
+            Some(QuickfixEntry {
+                path: path.to_path_buf(),
+                line: index + 1,
+                column: Some(found.start() + 1),
+                text: String::from_utf8_lossy(line).into_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Searches the files under the paths in parallel, sending the matches in each file (with paths
+/// without a leading `./`) to `tx` as soon as it's searched. Stops early once the receiver is gone.
+fn grep(args: &GrepArgs, tx: Sender<Vec<QuickfixEntry>>) {
+    let (first, rest) = args.paths.split_first().expect("at least one path");
+    let mut builder = WalkBuilder::new(first);
+    for path in rest {
+        builder.add(path);
+    }
+    builder
+        .standard_filters(!args.no_ignore)
+        .hidden(!args.hidden)
+        .overrides(args.globs.clone());
+
+    builder.build_parallel().run(|| {
+        let tx = tx.clone();
+        Box::new(move |entry| {
+            let Ok(entry) = entry else {
+                return WalkState::Continue;
+            };
+            if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+                return WalkState::Continue;
+            }
+            // Unreadable files are skipped, like those ignored
+            let Ok(contents) = fs::read(entry.path()) else {
+                return WalkState::Continue;
+            };
+            let path = entry.path();
+            let path = path.strip_prefix("./").unwrap_or(path);
+            let entries = search(path, &contents, &args.regex, args.text);
+            if entries.is_empty() {
+                return WalkState::Continue;
+            }
+            match tx.send(entries) {
+                Ok(()) => WalkState::Continue,
+                Err(_) => WalkState::Quit,
+            }
+        })
+    });
+}
+
+/// `builtin:grep [options] <pattern> [<path>...]`: prints `path:line:column:text` for the lines
+/// in the files under the paths (default .) matching the regex `pattern`, for a quickfix list.
+/// Like ag, hidden, ignored (e.g. by .gitignore) and binary files are skipped, and the case is
+/// ignored unless the pattern has an uppercase letter. Options: -F/--fixed-strings,
+/// -w/--word-regexp, -s/--case-sensitive, -i/--ignore-case, -g/--glob <glob> (only search
+/// matching files, or skip them with `!<glob>`), -H/--hidden, -I/--no-ignore, -a/--text
+pub fn grep_source(args: &[String]) -> Result<()> {
+    let args = GrepArgs::parse(args)?;
+    for path in &args.paths {
+        if !path.exists() {
+            return Err(eyre!("{} does not exist", path.display()));
+        }
+    }
+    print_as_found(
+        |tx| grep(&args, tx),
+        |out, entries| {
+            for entry in entries {
+                writeln!(out, "{entry}")?;
+            }
+            Ok(())
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn grep_sorted(dir: &Path, args: &[&str]) -> Result<Vec<String>> {
+        let mut args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        args.push(dir.display().to_string());
+        let args = GrepArgs::parse(&args)?;
+        let (tx, rx) = mpsc::channel();
+        grep(&args, tx);
+        let mut lines = rx
+            .into_iter()
+            .flatten()
+            .map(|entry| {
+                let path = entry.path.strip_prefix(dir).unwrap().to_path_buf();
+                QuickfixEntry { path, ..entry }.to_string()
+            })
+            .collect::<Vec<_>>();
+        lines.sort();
+        Ok(lines)
+    }
+
+    #[test]
+    fn test_grep() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path();
+        fs::create_dir_all(dir.join(".git"))?;
+        fs::create_dir_all(dir.join("src"))?;
+        fs::create_dir_all(dir.join("target"))?;
+        fs::write(
+            dir.join("src/main.rs"),
+            "fn main() {\r\n    Foo::new();\n}\n",
+        )?;
+        fs::write(dir.join("notes.md"), "foo.bar\nfoobar\n")?;
+        fs::write(dir.join("target/out.rs"), "foo\n")?;
+        fs::write(dir.join("data.bin"), b"foo\0\x01\n")?;
+        fs::write(dir.join(".gitignore"), "target/\n")?;
+
+        // Smart case
+        assert_eq!(
+            grep_sorted(dir, &["foo"])?,
+            vec![
+                "notes.md:1:1:foo.bar",
+                "notes.md:2:1:foobar",
+                "src/main.rs:2:5:    Foo::new();"
+            ]
+        );
+        assert_eq!(
+            grep_sorted(dir, &["Foo"])?,
+            vec!["src/main.rs:2:5:    Foo::new();"]
+        );
+        assert_eq!(grep_sorted(dir, &["-s", "foo", "-g", "*.rs"])?.len(), 0);
+        assert_eq!(
+            grep_sorted(dir, &["-F", "o.b"])?,
+            vec!["notes.md:1:3:foo.bar"]
+        );
+        assert_eq!(
+            grep_sorted(dir, &["-w", "foo", "-g", "!*.rs"])?,
+            vec!["notes.md:1:1:foo.bar"]
+        );
+        assert_eq!(grep_sorted(dir, &["main", "-g", "*.rs"])?.len(), 1);
+        assert_eq!(
+            grep_sorted(dir, &["-I", "-a", "^foo$"])?,
+            vec!["target/out.rs:1:1:foo"]
+        );
+        assert_eq!(
+            grep_sorted(dir, &["-I", "-a", "^foo"])?.len(),
+            4,
+            "binary and ignored files included"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let parse = |args: &[&str]| {
+            GrepArgs::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+        };
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["("]).is_err());
+        assert!(parse(&["-g"]).is_err());
+        assert!(parse(&["--nope", "x"]).is_err());
+        assert!(parse(&["-F", "("]).is_ok());
+    }
+}
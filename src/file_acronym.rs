@@ -0,0 +1,254 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use ignore::WalkBuilder;
+use regex::Regex;
+use std::path::Path;
+
+/// Acronym of part of a path: the first letter of each word, where words are split at camelCase,
+/// spaces and `/_.-` (e.g. `context_external_tool` => `cet`)
+fn fragment_acronym(fragment: &str) -> String {
+    let mut spaced = String::new();
+    let mut prev = None;
+    for c in fragment.chars() {
+        if prev.is_some_and(|prev: char| prev.is_ascii_lowercase()) && c.is_ascii_uppercase() {
+            spaced.push(' ');
+        }
+        if matches!(c, '/' | '_' | '.' | '-') {
+            spaced.push(' ');
+        }
+        spaced.push(c);
+        prev = Some(c);
+    }
+    spaced
+        .split_whitespace()
+        .filter_map(|word| {
+            let mut chars = word.chars();
+            match chars.next()? {
+                '-' | '_' => chars.next(),
+                first => Some(first),
+            }
+        })
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Acronym of a path: directories in uppercase, then the file name in lowercase, then extensions
+/// in uppercase (e.g. `app/models/context_external_tool.rb` => `AMcetR`)
+fn acronym(path: &str) -> String {
+    let mut parts = path.split('/').collect::<Vec<_>>();
+    let file = parts.pop().unwrap_or_default();
+    let mut file_parts = file.split('.');
+    let name = file_parts.next().unwrap_or_default();
+
+    let dirs = parts.into_iter().map(fragment_acronym).collect::<String>();
+    let extensions = file_parts.map(fragment_acronym).collect::<String>();
+    format!(
+        "{}{}{}",
+        dirs.to_uppercase(),
+        fragment_acronym(name),
+        extensions.to_uppercase()
+    )
+}
+
+/// Whether `needle` is at the end of `haystack`, just after an uppercase letter or `/`
+fn ends_after_word_start(haystack: &str, needle: &str) -> bool {
+    haystack
+        .strip_suffix(needle)
+        .and_then(|rest| rest.chars().last())
+        .is_some_and(|c| c.is_ascii_uppercase() || c == '/')
+}
+
+/// How well `needle` matches a path's acronym, if at all; higher is better. Looser matches are
+/// penalized by the acronym's length, and don't count once that leaves them no score above 0. For
+/// `AMcetR` (`app/models/context_external_tool.rb`):
+fn score(acronym: &str, needle: &str) -> Option<i32> {
+    let length = acronym.chars().count() as i32;
+    let without_extension = acronym.trim_end_matches(|c: char| c.is_ascii_uppercase());
+    let without_last_upper = acronym
+        .strip_suffix(|c: char| c.is_ascii_uppercase())
+        .unwrap_or_default();
+
+    let score = if acronym == needle {
+        // AMcetR
+        Some(100)
+    } else if without_last_upper == needle {
+        // AMcet
+        Some(90)
+    } else if ends_after_word_start(acronym, needle) {
+        // McetR, cetR
+        Some(80)
+    } else if ends_after_word_start(without_last_upper, needle) {
+        // Mcet, cet
+        Some(70)
+    } else if without_extension.to_lowercase().ends_with(needle) {
+        // mcet
+        Some(60 - length)
+    } else if acronym.to_lowercase().ends_with(needle) {
+        // cetr, mcetr, amcetr
+        Some(50 - length)
+    } else if acronym.contains(needle) {
+        // Mce, ce
+        Some(40 - length)
+    } else if acronym.to_lowercase().contains(&needle.to_lowercase()) {
+        // mce
+        Some(30 - length)
+    } else {
+        None
+    };
+    score.filter(|score| *score > 0)
+}
+
+/// Files under `dir`, skipping hidden and ignored (e.g. by `.gitignore`) files, as fd does
+fn walk_files(dir: &Path) -> Vec<String> {
+    WalkBuilder::new(dir)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+        })
+        .filter_map(|entry| {
+            let path = entry.path().strip_prefix("./").unwrap_or(entry.path());
+            path.to_str().map(str::to_owned)
+        })
+        .collect()
+}
+
+/// `<acronym>\t<path>` lines for the files in `paths` best matching `needle` (or for all files,
+/// without one)
+fn acronym_lines(paths: Vec<String>, needle: Option<&str>) -> Vec<String> {
+    let acronyms = paths.into_iter().map(|path| (acronym(&path), path));
+    let matches = match needle {
+        None => acronyms.collect::<Vec<_>>(),
+        Some(needle) => {
+            let scored = acronyms
+                .filter_map(|(acronym, path)| Some((score(&acronym, needle)?, acronym, path)))
+                .collect::<Vec<_>>();
+            let best = scored.iter().map(|(score, _, _)| *score).max();
+            scored
+                .into_iter()
+                .filter(|(score, _, _)| Some(*score) == best)
+                .map(|(_, acronym, path)| (acronym, path))
+                .collect()
+        }
+    };
+    matches
+        .into_iter()
+        .map(|(acronym, path)| format!("{acronym}\t{path}"))
+        .collect()
+}
+
+/// `builtin:acronym [<dir or path regex>] [<acronym>]`: prints `<acronym>\t<path>` for the files
+/// (under the directory, or with paths matching the regex) whose acronyms best match, e.g. `AMcetR`
+/// or `cet` for `app/models/context_external_tool.rb`
+pub fn acronym_source(args: &[String]) -> Result<()> {
+    let (pre_search, needle) = match args {
+        [] => (None, None),
+        [needle] => (None, Some(needle)),
+        [pre_search, needle] => (Some(pre_search), Some(needle)),
+        _ => {
+            return Err(eyre!(
+                "Usage: builtin:acronym [<dir or path regex>] [<acronym>]"
+            ))
+        }
+    };
+    let paths = match pre_search {
+        Some(dir) if Path::new(dir).is_dir() => walk_files(Path::new(dir)),
+        Some(pattern) => {
+            let re = Regex::new(pattern).wrap_err_with(|| format!("Invalid pattern {pattern}"))?;
+            let mut paths = walk_files(Path::new("."));
+            paths.retain(|path| re.is_match(path));
+            paths
+        }
+        None => walk_files(Path::new(".")),
+    };
+    let needle = needle
+        .map(String::as_str)
+        .filter(|needle| !needle.is_empty());
+    for line in acronym_lines(paths, needle) {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_acronym() {
+        assert_eq!(acronym("app/models/context_external_tool.rb"), "AMcetR");
+        assert_eq!(acronym("src/mode_args.rs"), "SmaR");
+        assert_eq!(acronym("lib/FooBar/fancy-thing.test.ts"), "LFBftTT");
+        assert_eq!(acronym("README"), "r");
+    }
+
+    #[test]
+    fn test_score_table() {
+        let table = [
+            ("AMcetR", Some(100)),
+            ("AMcet", Some(90)),
+            ("McetR", Some(80)),
+            ("cetR", Some(80)),
+            ("Mcet", Some(70)),
+            ("cet", Some(70)),
+            ("mcet", Some(60 - 6)),
+            ("cetr", Some(50 - 6)),
+            ("amcetr", Some(50 - 6)),
+            ("Mce", Some(40 - 6)),
+            ("mce", Some(30 - 6)),
+            ("xyz", None),
+        ];
+        for (needle, expected) in table {
+            assert_eq!(score("AMcetR", needle), expected, "{needle}");
+        }
+        // With a long enough acronym, loose matches no longer count
+        let long = format!("{}R", "AMcet".repeat(6));
+        assert_eq!(score(&long, "Mce"), Some(40 - 31));
+        assert_eq!(score(&long, "mce"), None);
+    }
+
+    #[test]
+    fn test_acronym_lines() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path();
+        fs::create_dir_all(dir.join(".git"))?;
+        for file in [
+            "app/models/context_external_tool.rb",
+            "app/models/context.rb",
+            "lib/context_external_tool.rb",
+            "tmp/cet.rb",
+        ] {
+            fs::create_dir_all(dir.join(file).parent().unwrap())?;
+            fs::write(dir.join(file), "")?;
+        }
+        fs::write(dir.join(".gitignore"), "tmp/\n")?;
+
+        let paths = walk_files(dir)
+            .into_iter()
+            .map(|path| path.replace(&format!("{}/", dir.display()), ""))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                "app/models/context.rb",
+                "app/models/context_external_tool.rb",
+                "lib/context_external_tool.rb"
+            ]
+        );
+        assert_eq!(
+            acronym_lines(paths.clone(), Some("cet")),
+            vec![
+                "AMcetR\tapp/models/context_external_tool.rb",
+                "LcetR\tlib/context_external_tool.rb"
+            ]
+        );
+        assert_eq!(
+            acronym_lines(paths, Some("AMc")),
+            vec!["AMcR\tapp/models/context.rb"]
+        );
+        Ok(())
+    }
+}
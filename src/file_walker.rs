@@ -0,0 +1,351 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use ignore::{DirEntry, WalkBuilder, WalkState};
+use regex::{Regex, RegexBuilder};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender, TryRecvError};
+use std::thread;
+
+/// Kinds of entry for `--type`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EntryType {
+    File,
+    Dir,
+    Symlink,
+}
+
+impl EntryType {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "f" | "file" => Ok(Self::File),
+            "d" | "dir" | "directory" => Ok(Self::Dir),
+            "l" | "symlink" => Ok(Self::Symlink),
+            _ => Err(eyre!(
+                "Unknown type {name} (expected f/file, d/dir/directory, l/symlink)"
+            )),
+        }
+    }
+
+    fn matches(self, entry: &DirEntry) -> bool {
+        entry.file_type().is_some_and(|kind| match self {
+            Self::File => kind.is_file(),
+            Self::Dir => kind.is_dir(),
+            Self::Symlink => kind.is_symlink(),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Pattern {
+    Regex(Regex),
+    Glob(glob::Pattern, glob::MatchOptions),
+}
+
+/// Options for `builtin:find`, mostly as in fd
+#[derive(Debug)]
+struct FindArgs {
+    pattern: Pattern,
+    roots: Vec<PathBuf>,
+    hidden: bool,
+    no_ignore: bool,
+    follow: bool,
+    full_path: bool,
+    types: Vec<EntryType>,
+    extensions: Vec<String>,
+    max_depth: Option<usize>,
+}
+
+impl FindArgs {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut hidden = false;
+        let mut no_ignore = false;
+        let mut follow = false;
+        let mut full_path = false;
+        let mut glob = false;
+        let mut fixed_strings = false;
+        let mut case_sensitive = None;
+        let mut types = vec![];
+        let mut extensions = vec![];
+        let mut max_depth = None;
+        let mut positional = vec![];
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value =
+                |flag: &str| args.next().ok_or_else(|| eyre!("{flag} requires a value"));
+            match arg.as_str() {
+                "-H" | "--hidden" => hidden = true,
+                "-I" | "--no-ignore" => no_ignore = true,
+                "-L" | "--follow" => follow = true,
+                "-p" | "--full-path" => full_path = true,
+                "-g" | "--glob" => glob = true,
+                "-F" | "--fixed-strings" => fixed_strings = true,
+                "-s" | "--case-sensitive" => case_sensitive = Some(true),
+                "-i" | "--ignore-case" => case_sensitive = Some(false),
+                "-t" | "--type" => types.push(EntryType::parse(value(arg)?)?),
+                "-e" | "--extension" => {
+                    extensions.push(value(arg)?.trim_start_matches('.').to_lowercase())
+                }
+                "-d" | "--max-depth" => {
+                    let depth = value(arg)?;
+                    max_depth = Some(
+                        depth
+                            .parse()
+                            .wrap_err_with(|| format!("Invalid depth {depth}"))?,
+                    );
+                }
+                flag if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(eyre!("Unknown option {flag} for builtin:find"))
+                }
+                _ => positional.push(arg.as_str()),
+            }
+        }
+
+        let (pattern, roots) = positional.split_first().unwrap_or((&"", &[]));
+        // Smart case, as in fd: case sensitive only if the pattern has an uppercase letter
+        let case_sensitive =
+            case_sensitive.unwrap_or_else(|| pattern.chars().any(char::is_uppercase));
+        let pattern = if glob {
+            let options = glob::MatchOptions {
+                case_sensitive,
+                require_literal_separator: true,
+                require_literal_leading_dot: false,
+            };
+            let glob = glob::Pattern::new(if pattern.is_empty() { "*" } else { pattern })
+                .wrap_err_with(|| format!("Invalid glob {pattern}"))?;
+            Pattern::Glob(glob, options)
+        } else {
+            let regex = if fixed_strings {
+                regex::escape(pattern)
+            } else {
+                pattern.to_string()
+            };
+            Pattern::Regex(
+                RegexBuilder::new(&regex)
+                    .case_insensitive(!case_sensitive)
+                    .build()
+                    .wrap_err_with(|| format!("Invalid pattern {pattern}"))?,
+            )
+        };
+        let roots = match roots {
+            [] => vec![PathBuf::from(".")],
+            roots => roots.iter().map(PathBuf::from).collect(),
+        };
+
+        Ok(Self {
+            pattern,
+            roots,
+            hidden,
+            no_ignore,
+            follow,
+            full_path,
+            types,
+            extensions,
+            max_depth,
+        })
+    }
+
+    fn matches(&self, entry: &DirEntry) -> bool {
+        if entry.depth() == 0 {
+            return false;
+        }
+        if !self.types.is_empty() && !self.types.iter().any(|kind| kind.matches(entry)) {
+            return false;
+        }
+        if !self.extensions.is_empty() {
+            let extension = entry
+                .path()
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase());
+            if !extension.is_some_and(|ext| self.extensions.contains(&ext)) {
+                return false;
+            }
+        }
+
+        let absolute;
+        let haystack = if self.full_path {
+            absolute = std::path::absolute(entry.path()).unwrap_or_else(|_| entry.path().into());
+            absolute.to_string_lossy()
+        } else {
+            entry.file_name().to_string_lossy()
+        };
+        match &self.pattern {
+            Pattern::Regex(regex) => regex.is_match(&haystack),
+            Pattern::Glob(glob, options) => glob.matches_with(&haystack, *options),
+        }
+    }
+}
+
+/// Walks the roots in parallel, sending each matching path (without a leading `./`) to `tx` as
+/// soon as it's found. Stops early once the receiver is gone.
+fn find(args: &FindArgs, tx: Sender<PathBuf>) {
+    let (first, rest) = args.roots.split_first().expect("at least one root");
+    let mut builder = WalkBuilder::new(first);
+    for root in rest {
+        builder.add(root);
+    }
+    builder
+        .standard_filters(!args.no_ignore)
+        .hidden(!args.hidden)
+        .follow_links(args.follow)
+        .max_depth(args.max_depth);
+
+    builder.build_parallel().run(|| {
+        let tx = tx.clone();
+        Box::new(move |entry| {
+            let Ok(entry) = entry else {
+                return WalkState::Continue;
+            };
+            if !args.matches(&entry) {
+                return WalkState::Continue;
+            }
+            let path = entry.path();
+            let path = path.strip_prefix("./").unwrap_or(path).to_path_buf();
+            match tx.send(path) {
+                Ok(()) => WalkState::Continue,
+                Err(_) => WalkState::Quit,
+            }
+        })
+    });
+}
+
+/// `builtin:find [options] [<pattern>] [<dir>...]`: prints files and directories under the dirs
+/// (default .) with names matching the regex `pattern`, like fd: hidden and ignored (e.g. by
+/// .gitignore) entries are skipped, and the case is ignored unless the pattern has an uppercase
+/// letter. Paths are printed as they are found, so a filter can start before the walk finishes.
+/// Options: -H/--hidden, -I/--no-ignore, -L/--follow, -t/--type f|d|l, -e/--extension <ext>,
+/// -d/--max-depth <n>, -g/--glob, -F/--fixed-strings, -p/--full-path, -s/--case-sensitive,
+/// -i/--ignore-case
+pub fn find_source(args: &[String]) -> Result<()> {
+    let args = FindArgs::parse(args)?;
+    for root in &args.roots {
+        if !Path::new(root).is_dir() {
+            return Err(eyre!("{} is not a directory", root.display()));
+        }
+    }
+    print_as_found(
+        |tx| find(&args, tx),
+        |out, path| {
+            out.write_all(path.as_os_str().as_encoded_bytes())?;
+            out.write_all(b"\n")
+        },
+    )
+}
+
+/// Runs `produce` on another thread, writing what it sends with `write` as soon as it arrives, so
+/// a filter reading the output can start before `produce` finishes. `produce` should stop once
+/// sending fails, which happens when the filter exits.
+pub fn print_as_found<T: Send>(
+    produce: impl FnOnce(Sender<T>) + Send,
+    write: impl Fn(&mut dyn Write, T) -> std::io::Result<()>,
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        scope.spawn(|| produce(tx));
+
+        let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+        let result = (|| -> std::io::Result<()> {
+            loop {
+                let item = match rx.try_recv() {
+                    Ok(item) => item,
+                    // Flush while waiting, so the filter gets results as they're found
+                    Err(TryRecvError::Empty) => {
+                        stdout.flush()?;
+                        match rx.recv() {
+                            Ok(item) => item,
+                            Err(_) => break,
+                        }
+                    }
+                    Err(TryRecvError::Disconnected) => break,
+                };
+                write(&mut stdout, item)?;
+            }
+            stdout.flush()
+        })();
+        // Dropping the receiver stops `produce`
+        drop(rx);
+        match result {
+            // The filter exited (e.g. fzf after a choice was made)
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => Ok(()),
+            result => result.wrap_err("Failed to print results"),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn find_sorted(dir: &Path, args: &[&str]) -> Result<Vec<String>> {
+        let mut args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        args.push(dir.display().to_string());
+        let args = FindArgs::parse(&args)?;
+        let (tx, rx) = mpsc::channel();
+        find(&args, tx);
+        let mut paths = rx
+            .into_iter()
+            .map(|path| path.strip_prefix(dir).unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        paths.sort();
+        Ok(paths)
+    }
+
+    #[test]
+    fn test_find() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path();
+        fs::create_dir_all(dir.join(".git"))?;
+        for file in [
+            "src/main.rs",
+            "src/Lib.rs",
+            "README.md",
+            "target/debug/out.rs",
+            ".hidden.rs",
+        ] {
+            fs::create_dir_all(dir.join(file).parent().unwrap())?;
+            fs::write(dir.join(file), "")?;
+        }
+        fs::write(dir.join(".gitignore"), "target/\n")?;
+
+        assert_eq!(
+            find_sorted(dir, &["."])?,
+            vec!["README.md", "src", "src/Lib.rs", "src/main.rs"]
+        );
+        // Smart case
+        assert_eq!(find_sorted(dir, &["lib"])?, vec!["src/Lib.rs"]);
+        assert!(find_sorted(dir, &["LIB"])?.is_empty());
+        assert_eq!(
+            find_sorted(dir, &["-H", "-I", "-e", "rs", ""])?,
+            vec![
+                ".hidden.rs",
+                "src/Lib.rs",
+                "src/main.rs",
+                "target/debug/out.rs"
+            ]
+        );
+        assert_eq!(find_sorted(dir, &["-t", "d", ""])?, vec!["src"]);
+        assert_eq!(
+            find_sorted(dir, &["-d", "1", "-t", "f", ""])?,
+            vec!["README.md"]
+        );
+        assert_eq!(find_sorted(dir, &["-g", "*.md"])?, vec!["README.md"]);
+        assert_eq!(find_sorted(dir, &["-F", "n.rs"])?, vec!["src/main.rs"]);
+        assert_eq!(
+            find_sorted(dir, &["-p", "src/.*rs$"])?,
+            vec!["src/Lib.rs", "src/main.rs"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let parse = |args: &[&str]| {
+            FindArgs::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+        };
+        assert!(parse(&["-t", "x"]).is_err());
+        assert!(parse(&["-d"]).is_err());
+        assert!(parse(&["("]).is_err());
+        assert!(parse(&["--nope"]).is_err());
+    }
+}
@@ -0,0 +1,370 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Keys for choosing a file with `--numbered`, in order
+const NUMBER_KEYS: &str = "123456789abcdefghijklmnopqrstuvwxyz";
+const CTRL_C: u8 = 0x03;
+
+/// A file changed in the worktree, the index or the commit, with each one's status letter (as in
+/// `git status --short`, e.g. `M`, `A`, `R`, or `?` for untracked)
+#[derive(Debug, Default, PartialEq)]
+struct GitFile {
+    /// Relative to the repository root
+    path: String,
+    renamed_from: Option<String>,
+    worktree: Option<char>,
+    index: Option<char>,
+    commit: Option<char>,
+}
+
+/// Runs git in `dir`, returning its stdout
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .wrap_err("Failed to run git")?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+fn file_mut<'a>(files: &'a mut Vec<GitFile>, path: &str) -> &'a mut GitFile {
+    match files.iter().position(|file| file.path == path) {
+        Some(i) => &mut files[i],
+        None => {
+            files.push(GitFile {
+                path: path.to_owned(),
+                ..Default::default()
+            });
+            files.last_mut().expect("just pushed")
+        }
+    }
+}
+
+/// Adds files from `git status --porcelain -z`: `XY <path>` entries, followed by the original path
+/// for renames and copies
+fn add_status(files: &mut Vec<GitFile>, status: &str) {
+    let mut entries = status.split('\0').filter(|entry| !entry.is_empty());
+    while let Some(entry) = entries.next() {
+        let mut chars = entry.chars();
+        let (Some(x), Some(y), Some(path)) = (chars.next(), chars.next(), entry.get(3..)) else {
+            continue;
+        };
+        let renamed_from = matches!(x, 'R' | 'C').then(|| entries.next()).flatten();
+        let file = file_mut(files, path);
+        if x == '?' {
+            file.worktree = Some('?');
+            continue;
+        }
+        file.index = Some(x).filter(|x| *x != ' ');
+        file.worktree = Some(y).filter(|y| *y != ' ');
+        if let Some(from) = renamed_from {
+            file.renamed_from = Some(from.to_owned());
+        }
+    }
+}
+
+/// Adds files from `git show --name-status -z`: a status (e.g. `M`, or `R100` followed by the old
+/// path) and then the path
+fn add_commit(files: &mut Vec<GitFile>, name_status: &str) {
+    let mut fields = name_status.split('\0').filter(|field| !field.is_empty());
+    while let Some(status) = fields.next() {
+        let Some(letter) = status.chars().next() else {
+            continue;
+        };
+        let renamed_from = match letter {
+            'R' | 'C' => fields.next(),
+            _ => None,
+        };
+        let Some(path) = fields.next() else {
+            break;
+        };
+        let file = file_mut(files, path);
+        file.commit = Some(letter);
+        if file.renamed_from.is_none() {
+            file.renamed_from = renamed_from.map(str::to_owned);
+        }
+    }
+}
+
+/// The repository root and the files changed in `git_ref`, or (without one) in the worktree, the
+/// index and the last commit. Files which no longer exist are left out; the most recently modified
+/// come first.
+fn git_files(dir: &Path, git_ref: Option<&str>) -> Result<(PathBuf, Vec<GitFile>)> {
+    let root = PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"])?.trim_end());
+    let mut files = vec![];
+    if git_ref.is_none() {
+        add_status(
+            &mut files,
+            &git(
+                dir,
+                &["status", "--porcelain", "-z", "--untracked-files=all"],
+            )?,
+        );
+    }
+    let show = ["show", "-z", "--name-status", "--format=", "--find-renames"];
+    match git_ref {
+        Some(git_ref) => add_commit(
+            &mut files,
+            &git(dir, &[&show[..], &[git_ref, "--"]].concat())?,
+        ),
+        // A new repository has no commit yet
+        None => {
+            if let Ok(name_status) = git(dir, &[&show[..], &["HEAD", "--"]].concat()) {
+                add_commit(&mut files, &name_status);
+            }
+        }
+    }
+
+    let mut files = files
+        .into_iter()
+        .filter_map(|file| {
+            let modified = root.join(&file.path).metadata().ok()?.modified().ok()?;
+            Some((modified, file))
+        })
+        .collect::<Vec<_>>();
+    files.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    Ok((root, files.into_iter().map(|(_, file)| file).collect()))
+}
+
+fn paint(color: bool, code: &str, text: &str) -> String {
+    if color {
+        format!("\x1b[{code}m{text}\x1b[0m")
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Label for a status in a column, e.g. `loc` for a file modified in the worktree
+fn status_label(status: Option<char>, modified_label: &'static str) -> &'static str {
+    match status {
+        None => "   ",
+        Some('?') => "new",
+        Some('A') => "add",
+        Some('R') => "ren",
+        Some('C') => "cpy",
+        Some('D') => "del",
+        Some(_) => modified_label,
+    }
+}
+
+/// e.g. `loc ind     src/main.rs`: where the file is changed (worktree, index, commit) and its path
+fn display(file: &GitFile, color: bool) -> String {
+    let mut display = [
+        paint(color, "31", status_label(file.worktree, "loc")),
+        paint(color, "34", status_label(file.index, "ind")),
+        paint(color, "32", status_label(file.commit, "com")),
+        paint(color, "1", &file.path),
+    ]
+    .join(" ");
+    if let Some(from) = &file.renamed_from {
+        display.push_str(&paint(color, "2", &format!(" (from {from})")));
+    }
+    display
+}
+
+/// e.g. `1a2b3c4 Fix the thing`
+fn commit_summary(dir: &Path, git_ref: &str, color: bool) -> Option<String> {
+    let summary = git(dir, &["log", "-1", "--format=%h%x09%s", git_ref, "--"]).ok()?;
+    let (hash, subject) = summary.trim_end().split_once('\t')?;
+    Some(format!("{} {subject}", paint(color, "33", hash)))
+}
+
+/// Reads a single key press from the terminal
+fn read_key() -> Result<u8> {
+    let tty = File::open("/dev/tty").wrap_err("Failed to open terminal")?;
+    let fd = tty.as_raw_fd();
+    // SAFETY: termios is plain data, filled in by tcgetattr
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    // SAFETY: fd is an open terminal and original is a valid termios
+    if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+        return Err(eyre!("Failed to read terminal settings"));
+    }
+    let mut raw = original;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+    // SAFETY: as above; the settings are restored below
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) };
+    let mut key = [0];
+    let res = (&tty).read_exact(&mut key);
+    // SAFETY: restoring the settings read above
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    res?;
+    Ok(key[0])
+}
+
+/// Prints the files with a key to choose each, and appends the chosen one to `output`
+fn choose_numbered(
+    root: &Path,
+    files: &[GitFile],
+    summary: Option<String>,
+    output: &Path,
+) -> Result<()> {
+    let color = std::env::var_os("NO_COLOR").is_none();
+    if let Some(summary) = summary {
+        println!("{summary}");
+    }
+    for (file, key) in files.iter().zip(NUMBER_KEYS.chars()) {
+        let badge = paint(color, "1;3;30;46", &format!(" {key}\u{a0}"));
+        println!("{} {badge}", display(file, color));
+    }
+    print!("? ");
+    std::io::stdout().flush()?;
+    let key = read_key()?;
+    println!();
+    if key == CTRL_C {
+        return Err(eyre!("Cancelled"));
+    }
+    let file = NUMBER_KEYS
+        .find(key as char)
+        .and_then(|i| files.get(i))
+        .ok_or_else(|| eyre!("No file for key {}", key as char))?;
+    let mut output = OpenOptions::new().append(true).create(true).open(output)?;
+    writeln!(output, "{}", root.join(&file.path).display())?;
+    Ok(())
+}
+
+/// `builtin:git-files [--raw | --numbered <output file>] [<ref>]`: files changed in the worktree,
+/// the index and the last commit (or only in `<ref>`), most recently modified first. Prints
+/// `<absolute path>\t<display>` lines (for fzf's `--with-nth`), followed by a line showing the
+/// commit; with `--raw`, only paths relative to the repository root; with `--numbered`, prompts
+/// for a key to choose a file.
+pub fn git_files_source(args: &[String]) -> Result<()> {
+    let mut raw = false;
+    let mut numbered_output = None;
+    let mut git_ref = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--raw" => raw = true,
+            "--numbered" => {
+                let output = args
+                    .next()
+                    .ok_or_else(|| eyre!("--numbered requires an output file"))?;
+                numbered_output = Some(PathBuf::from(output));
+            }
+            flag if flag.starts_with("--") => {
+                return Err(eyre!(
+                    "Unknown option {flag} for builtin:git-files (expected --raw, --numbered)"
+                ))
+            }
+            _ if git_ref.is_none() => git_ref = Some(arg.as_str()),
+            _ => return Err(eyre!("builtin:git-files takes at most one ref")),
+        }
+    }
+
+    let cwd = std::env::current_dir()?;
+    let (root, files) = git_files(&cwd, git_ref)?;
+    let color = std::env::var_os("NO_COLOR").is_none();
+    let summary = commit_summary(&cwd, git_ref.unwrap_or("HEAD"), color);
+    if let Some(output) = numbered_output {
+        return choose_numbered(&root, &files, summary, &output);
+    }
+    for file in &files {
+        if raw {
+            println!("{}", file.path);
+        } else {
+            println!(
+                "{}\t{}",
+                root.join(&file.path).display(),
+                display(file, color)
+            );
+        }
+    }
+    if let (false, Some(summary)) = (raw, summary) {
+        println!("\t{summary}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn run_git(dir: &Path, args: &[&str]) {
+        let args = [&["-c", "user.name=t", "-c", "user.email=t@t"], args].concat();
+        git(dir, &args).unwrap();
+    }
+
+    #[test]
+    fn test_git_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path();
+        run_git(dir, &["init", "-q"]);
+        for file in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(dir.join(file), format!("{file}\n"))?;
+        }
+        run_git(dir, &["add", "."]);
+        run_git(dir, &["commit", "-qm", "first"]);
+        fs::write(dir.join("b.txt"), "changed\n")?;
+        run_git(dir, &["mv", "c.txt", "d.txt"]);
+        run_git(dir, &["commit", "-qam", "second"]);
+        fs::write(dir.join("a.txt"), "changed\n")?;
+        fs::write(dir.join("e.txt"), "e\n")?;
+        run_git(dir, &["add", "e.txt"]);
+        fs::write(dir.join("f.txt"), "f\n")?;
+
+        let (root, mut files) = git_files(dir, None)?;
+        assert_eq!(root, dir.canonicalize()?);
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let statuses = files
+            .iter()
+            .map(|file| (file.path.as_str(), file.worktree, file.index, file.commit))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                ("a.txt", Some('M'), None, None),
+                ("b.txt", None, None, Some('M')),
+                ("d.txt", None, None, Some('R')),
+                ("e.txt", None, Some('A'), None),
+                ("f.txt", Some('?'), None, None),
+            ]
+        );
+        assert_eq!(files[2].renamed_from.as_deref(), Some("c.txt"));
+        assert_eq!(display(&files[2], false), "        ren d.txt (from c.txt)");
+
+        let (_, files) = git_files(dir, Some("@^"))?;
+        let mut paths = files
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect::<Vec<_>>();
+        paths.sort();
+        // c.txt no longer exists
+        assert_eq!(paths, vec!["a.txt", "b.txt"]);
+        assert!(files.iter().all(|file| file.commit == Some('A')));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_status_renames() {
+        let mut files = vec![];
+        add_status(&mut files, "R  new.rs\0old.rs\0 M other.rs\0");
+        assert_eq!(
+            files,
+            vec![
+                GitFile {
+                    path: "new.rs".to_owned(),
+                    renamed_from: Some("old.rs".to_owned()),
+                    index: Some('R'),
+                    ..Default::default()
+                },
+                GitFile {
+                    path: "other.rs".to_owned(),
+                    worktree: Some('M'),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+}
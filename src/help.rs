@@ -0,0 +1,367 @@
+use crate::builtins::{BUILTINS, BUILTIN_PREFIX};
+use crate::config::{get_config, parse_mode_spec, AppConfig, Mode, Script};
+use crate::mode_args;
+use color_eyre::eyre::{eyre, Result};
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+
+/// Heading for modes without a `category`
+const UNCATEGORIZED: &str = "Other modes";
+
+/// Bold/colored output when stdout is a terminal (and NO_COLOR isn't set), plain text otherwise
+struct Style {
+    enabled: bool,
+}
+
+impl Style {
+    fn new() -> Self {
+        Self {
+            enabled: std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        }
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_owned()
+        }
+    }
+
+    fn heading(&self, text: &str) -> String {
+        self.paint("1;4", text)
+    }
+
+    fn mode(&self, text: &str) -> String {
+        self.paint("1;32", text)
+    }
+
+    fn label(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+}
+
+/// How a mode/alias name is given in the first argument: as-is for one letter, otherwise `:name`
+pub fn mode_spec_for_name(name: &str) -> String {
+    if name.chars().count() == 1 {
+        name.to_owned()
+    } else {
+        format!(":{name}")
+    }
+}
+
+/// Sort key for mode names: single letters first, case-insensitively with lowercase before
+/// uppercase (a, A, b, B, ...), then long names
+pub fn mode_sort_key(name: &str) -> (bool, String, bool) {
+    (
+        name.chars().count() > 1,
+        name.to_lowercase(),
+        name.chars().next().is_some_and(char::is_uppercase),
+    )
+}
+
+/// A script as it would be typed in a shell. Arguments with whitespace or quotes are
+/// single-quoted.
+fn script_to_string(script: &Script) -> String {
+    let quote = |arg: &String| {
+        if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || "'\"$\\".contains(c)) {
+            format!("'{}'", arg.replace('\'', r"'\''"))
+        } else {
+            arg.clone()
+        }
+    };
+    match script {
+        Script::Command(command) => command.clone(),
+        Script::CommandWithArgs(args) => args.iter().map(quote).collect::<Vec<_>>().join(" "),
+    }
+}
+
+/// Prints `label: value`, indenting any further lines of a multi-line value
+fn print_field(style: &Style, label: &str, value: &str) {
+    let mut lines = value.lines();
+    println!(
+        "  {} {}",
+        style.label(&format!("{label}:")),
+        lines.next().unwrap_or("")
+    );
+    for line in lines {
+        println!("      {line}");
+    }
+}
+
+fn print_general_help(style: &Style) {
+    println!("voxide -- flexible, customizable file finder/opener");
+    println!("voxide finds files and line numbers and opens them (via arguments to your");
+    println!("editor or a quickfix file) using scripts/commands/transforms defined in the");
+    println!("configuration file.");
+    println!();
+    println!("All invocations begin with the default (base) mode defined in the config file under");
+    println!("`default_mode`. Any further modes (letters) in the first argument merge (layer) on");
+    println!("top of this to determine the final mode's cmd, script, etc.");
+    println!();
+    println!("If stdin is piped and `stdin_mode` is set in the config, those mode letters are");
+    println!("merged in first, so e.g. `cargo build 2>&1 | voxide` runs stdin through the");
+    println!("transforms.");
+
+    println!();
+    println!("Run 'voxide --init' to initialize with a default config and scripts.");
+    println!("Run 'voxide --readme' to print out the README.md file compiled into voxide.");
+    println!("Run 'voxide --trust' to trust the project configs (.voxide.toml or");
+    println!(".config/voxide.toml, or .json/.yaml) found from here up to the repository root.");
+    println!("Run RUST_LOG=debug voxide... to show debugging info.");
+    println!();
+    println!("{}", style.heading("Usage:"));
+    println!("  voxide [<mode letter(s)>] [<arguments to mode scripts> ...]");
+    println!("  voxide --mode <mode or alias name> [<arguments to mode scripts> ...]");
+    println!("  voxide --help [<mode letter(s) or name>]");
+    println!();
+    println!("Config is read from the *.toml, *.json and *.yaml files in $XDG_CONFIG_HOME/voxide");
+    println!("(~/.config/voxide), merged in sorted order regardless of format,");
+    println!("or from the directories (or files) in VOXIDE_CONFIG_DIR, separated by ':', lowest");
+    println!("precedence first. '--config <dir or file>' (which can be repeated) before any other");
+    println!("arguments overrides both. Config files can also 'include = [...]' other files");
+    println!("(globs allowed, relative to the including file). System-wide config in");
+    println!("$XDG_CONFIG_DIRS/voxide (/etc/xdg/voxide) is loaded first. Run");
+    println!("'voxide --config-sources' to list the config files used and where modes come from.");
+    println!(
+        "Run 'voxide --check-config' to check the config for errors, or 'voxide --print-schema'"
+    );
+    println!("to print a JSON Schema for config files (e.g. for editor completion).");
+    println!("Run 'voxide --completions <bash|zsh|fish|tabry> [<command name>]' to print a shell");
+    println!(
+        "completion script for the modes in your config, or 'voxide --init-shell <bash|zsh|fish>'"
+    );
+    println!("for a `v` function, a `vcd` function which cds to a directory result, an Alt-v");
+    println!("keybinding and completions.");
+    println!();
+    println!("Modes and aliases with longer names can be given in the first argument as :name,");
+    println!("e.g. 'voxide z:rails-routes' (a name runs until the next ':').");
+    println!();
+    println!("Builtin sources, used as e.g. `script = \"builtin:history\"`:");
+    for (name, desc) in BUILTINS {
+        println!("  {BUILTIN_PREFIX}{name} {desc}");
+    }
+}
+
+fn print_mode_list(style: &Style, config: &AppConfig) {
+    let mut by_category: BTreeMap<Option<&str>, Vec<(&String, &Mode)>> = BTreeMap::new();
+    for (name, mode) in &config.modes {
+        by_category
+            .entry(mode.category.as_deref())
+            .or_default()
+            .push((name, mode));
+    }
+    let only_uncategorized = by_category.keys().all(Option::is_none);
+    // Uncategorized modes go last
+    let mut categories = by_category.into_iter().collect::<Vec<_>>();
+    categories.sort_by_key(|(category, _)| category.is_none());
+
+    for (category, mut modes) in categories {
+        let heading = match category {
+            Some(category) => category,
+            None if only_uncategorized => "Available modes",
+            None => UNCATEGORIZED,
+        };
+        println!();
+        println!("{}", style.heading(&format!("{heading}:")));
+        modes.sort_by_key(|(name, _)| mode_sort_key(name));
+        for (name, mode) in modes {
+            let usage = match &mode.arg_spec {
+                Some(arg_spec) => format!(" {}", mode_args::usage(arg_spec)),
+                None => String::new(),
+            };
+            println!(
+                "  {}{usage}:  {}",
+                style.mode(&mode_spec_for_name(name)),
+                mode.name
+            );
+            println!("      {}", mode.desc.lines().next().unwrap_or(""));
+        }
+    }
+
+    if !config.aliases.is_empty() {
+        println!();
+        println!("{}", style.heading("Aliases:"));
+        let mut aliases = config.aliases.iter().collect::<Vec<_>>();
+        aliases.sort_by_key(|(name, _)| mode_sort_key(name));
+        for (name, spec) in aliases {
+            println!("  {}:  {spec}", style.mode(&mode_spec_for_name(name)));
+        }
+    }
+
+    println!();
+    println!("Run 'voxide --help <mode>' for a mode's full description, arguments and examples.");
+}
+
+fn print_mode_help(style: &Style, name: &str, mode: &Mode) {
+    let category = match &mode.category {
+        Some(category) => format!(" ({category})"),
+        None => String::new(),
+    };
+    println!(
+        "{}:  {}{category}",
+        style.mode(&mode_spec_for_name(name)),
+        style.label(&mode.name)
+    );
+    for line in mode.desc.lines() {
+        println!("  {line}");
+    }
+    println!();
+
+    let mut usage = format!("voxide {}", mode_spec_for_name(name));
+    if let Some(arg_spec) = &mode.arg_spec {
+        usage.push_str(&format!(" {}", mode_args::usage(arg_spec)));
+    } else if mode.script.is_some() {
+        usage.push_str(" [<arguments to script> ...]");
+    }
+    print_field(style, "Usage", &usage);
+    for arg in mode.arg_spec.iter().flatten() {
+        println!("      {}", arg.describe());
+    }
+
+    if let Some(script) = &mode.script {
+        let mut details = vec![];
+        if mode.quickfix.unwrap_or(false) {
+            details.push("outputs a quickfix list".to_owned());
+        }
+        if mode.script_uses_tempfile.unwrap_or(false) {
+            details.push("writes to a tempfile".to_owned());
+        }
+        if let Some(timeout) = mode.timeout {
+            details.push(format!("timeout {timeout}s"));
+        }
+        let mut value = script_to_string(script);
+        if let Some(args) = &mode.args {
+            value.push_str(&format!(" {}", args.join(" ")));
+        }
+        if !details.is_empty() {
+            value = format!("({}) {value}", details.join(", "));
+        }
+        print_field(style, "Script", &value);
+    }
+    if let Some(filter) = &mode.filter {
+        let mut value = script_to_string(filter);
+        if let Some(timeout) = mode.filter_timeout {
+            value = format!("(timeout {timeout}s) {value}");
+        }
+        print_field(style, "Filter", &value);
+    }
+    for (label, cmd) in [
+        ("Cmd", &mode.cmd),
+        ("Dir cmd", &mode.dir_cmd),
+        ("Quickfix cmd", &mode.quickfix_cmd),
+    ] {
+        if let Some(cmd) = cmd {
+            print_field(style, label, cmd);
+        }
+    }
+    if let Some(cwd) = &mode.cwd {
+        print_field(style, "Cwd", cwd);
+    }
+    if let Some(env) = &mode.env {
+        let mut env = env.iter().collect::<Vec<_>>();
+        env.sort();
+        let env = env
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>();
+        print_field(style, "Env", &env.join("\n"));
+    }
+
+    if let Some(examples) = &mode.examples {
+        println!();
+        println!("  {}", style.label("Examples:"));
+        for example in examples {
+            println!("    {example}");
+        }
+    }
+}
+
+/// Prints help for each mode in `spec` (letters, `:name`s, a long name or an alias), expanding
+/// aliases
+fn print_spec_help(
+    style: &Style,
+    config: &AppConfig,
+    spec: &str,
+    aliases_seen: &mut Vec<String>,
+) -> Result<()> {
+    // A bare long name doesn't need the leading ':'
+    let names = if config.modes.contains_key(spec) || config.aliases.contains_key(spec) {
+        vec![spec]
+    } else {
+        parse_mode_spec(spec)?
+    };
+    for name in names {
+        if let Some(mode) = config.modes.get(name) {
+            print_mode_help(style, name, mode);
+            println!();
+        } else if let Some(alias_spec) = config.aliases.get(name) {
+            if aliases_seen.iter().any(|seen| seen == name) {
+                return Err(eyre!(
+                    "Alias cycle: {} -> {name}",
+                    aliases_seen.join(" -> ")
+                ));
+            }
+            println!(
+                "{}:  alias for {}",
+                style.mode(&mode_spec_for_name(name)),
+                style.label(alias_spec)
+            );
+            println!();
+            aliases_seen.push(name.to_owned());
+            print_spec_help(style, config, alias_spec, aliases_seen)?;
+            aliases_seen.pop();
+        } else {
+            return Err(eyre!("No mode or alias found for {name}"));
+        }
+    }
+    Ok(())
+}
+
+/// Prints general help and the list of modes, or with `spec`, detailed help for those modes
+pub fn help(spec: Option<&str>) -> Result<()> {
+    let style = Style::new();
+    let config_res = get_config();
+
+    if let Some(spec) = spec {
+        let config = config_res?;
+        return print_spec_help(&style, &config, spec, &mut vec![]);
+    }
+
+    print_general_help(&style);
+    match config_res {
+        Ok(config) => print_mode_list(&style, &config),
+        Err(_) => {
+            println!();
+            println!("No valid config available; run with no arguments to get full error or run with --init to initialize with default config and scripts, or --help for more info.");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_sort_key() {
+        let mut names = vec!["rails-routes", "B", "a", "b", "A", "-"];
+        names.sort_by_key(|name| mode_sort_key(name));
+        assert_eq!(names, vec!["-", "a", "A", "b", "B", "rails-routes"]);
+    }
+
+    #[test]
+    fn test_script_to_string() {
+        let script = Script::CommandWithArgs(vec![
+            "fish".to_owned(),
+            "-c".to_owned(),
+            "type -p $argv".to_owned(),
+            "it's".to_owned(),
+        ]);
+        assert_eq!(
+            script_to_string(&script),
+            r"fish -c 'type -p $argv' 'it'\''s'"
+        );
+    }
+}
@@ -0,0 +1,432 @@
+use crate::path_util;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use log::debug;
+use regex::Regex;
+use std::fs;
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File in the data dir recording opened files, as lines of
+/// "<rank>\t<last access>\t<line>\t<mode>\t<vcs root>\t<path>"
+const HISTORY_FILE: &str = "history";
+/// File next to the history locked while it's updated, so concurrent voxides don't lose entries
+const LOCK_FILE: &str = "history.lock";
+/// When the ranks add up to more than this, they are all scaled down by `AGING_FACTOR` (dropping
+/// any that fall below 1), so files not opened in a while fade out
+const MAX_TOTAL_RANK: f64 = 10000.0;
+const AGING_FACTOR: f64 = 0.9;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// A file (or directory) opened by voxide, after transforms
+#[derive(Debug, PartialEq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    path: PathBuf,
+    line: Option<usize>,
+    vcs_root: Option<PathBuf>,
+    /// Mode letters used the last time the file was opened
+    mode: String,
+    rank: f64,
+    /// Seconds since the epoch
+    last_access: u64,
+}
+
+impl Entry {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(6, '\t');
+        let rank = fields.next()?.parse().ok()?;
+        let last_access = fields.next()?.parse().ok()?;
+        let line_number = fields.next()?.parse().ok();
+        let mode = fields.next()?.to_owned();
+        let vcs_root = Some(fields.next()?)
+            .filter(|root| !root.is_empty())
+            .map(PathBuf::from);
+        let path = PathBuf::from(fields.next()?);
+        Some(Self {
+            path,
+            line: line_number,
+            vcs_root,
+            mode,
+            rank,
+            last_access,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.rank,
+            self.last_access,
+            self.line.map(|line| line.to_string()).unwrap_or_default(),
+            self.mode,
+            self.vcs_root
+                .as_deref()
+                .map(|root| root.display().to_string())
+                .unwrap_or_default(),
+            self.path.display()
+        )
+    }
+
+    /// Rank weighted by how recently the file was opened (as in zoxide)
+    fn frecency(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_access);
+        let weight = match age {
+            age if age < HOUR => 4.0,
+            age if age < DAY => 2.0,
+            age if age < WEEK => 0.5,
+            _ => 0.25,
+        };
+        self.rank * weight
+    }
+}
+
+#[derive(Debug, Default)]
+struct History {
+    entries: Vec<Entry>,
+}
+
+impl History {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read history {}", path.display()))?;
+        let entries = contents
+            .lines()
+            .filter_map(|line| {
+                let entry = Entry::parse(line);
+                if entry.is_none() {
+                    debug!("Skipping invalid history line: {line}");
+                }
+                entry
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// Writes to a tempfile which replaces the history file, so a concurrent reader never sees a
+    /// partial file
+    fn save(&self, path: &Path) -> Result<()> {
+        let dir = path
+            .parent()
+            .ok_or_else(|| eyre!("Invalid history path {}", path.display()))?;
+        fs::create_dir_all(dir)?;
+        let mut tempfile = tempfile::NamedTempFile::new_in(dir)?;
+        for entry in &self.entries {
+            writeln!(tempfile, "{}", entry.to_line())?;
+        }
+        tempfile
+            .persist(path)
+            .wrap_err_with(|| format!("Failed to write history {}", path.display()))?;
+        Ok(())
+    }
+
+    fn add(&mut self, location: &Location, mode: &str, now: u64) {
+        let vcs_root = location
+            .path
+            .parent()
+            .and_then(path_util::vcs_root)
+            .map(Path::to_path_buf);
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.path == location.path)
+        {
+            Some(entry) => {
+                entry.rank += 1.0;
+                entry.last_access = now;
+                entry.line = location.line;
+                entry.vcs_root = vcs_root;
+                entry.mode = mode.to_owned();
+            }
+            None => self.entries.push(Entry {
+                path: location.path.clone(),
+                line: location.line,
+                vcs_root,
+                mode: mode.to_owned(),
+                rank: 1.0,
+                last_access: now,
+            }),
+        }
+    }
+
+    fn age(&mut self) {
+        let total: f64 = self.entries.iter().map(|entry| entry.rank).sum();
+        if total > MAX_TOTAL_RANK {
+            for entry in &mut self.entries {
+                entry.rank *= AGING_FACTOR;
+            }
+            self.entries.retain(|entry| entry.rank >= 1.0);
+        }
+    }
+
+    /// Entries, most frecent first
+    fn ranked(&self, now: u64) -> Vec<&Entry> {
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.frecency(now).total_cmp(&a.frecency(now)));
+        entries
+    }
+}
+
+fn history_path() -> Result<PathBuf> {
+    path_util::data_dir().map(|dir| dir.join(HISTORY_FILE))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Takes an exclusive advisory lock on the lock file next to the history at `path`, waiting for
+/// any other voxide updating it. The lock is released when the returned file is dropped.
+fn lock(path: &Path) -> Result<fs::File> {
+    let lock_path = path.with_file_name(LOCK_FILE);
+    if let Some(dir) = lock_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .wrap_err_with(|| format!("Failed to open {}", lock_path.display()))?;
+    // SAFETY: the fd is valid for as long as `file` is open
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error())
+            .wrap_err_with(|| format!("Failed to lock {}", lock_path.display()));
+    }
+    Ok(file)
+}
+
+/// Records the files opened (with the mode letters used) in the history
+pub fn record(locations: &[Location], mode: &str) -> Result<()> {
+    record_at(&history_path()?, locations, mode, now())
+}
+
+fn record_at(path: &Path, locations: &[Location], mode: &str, now: u64) -> Result<()> {
+    let _lock = lock(path)?;
+    let mut history = History::load(path)?;
+    for location in locations {
+        let Ok(canonical) = location.path.canonicalize() else {
+            debug!("Not recording missing file {}", location.path.display());
+            continue;
+        };
+        // Tabs and newlines would break the history file
+        if canonical
+            .to_str()
+            .is_none_or(|path| path.contains(['\t', '\n']))
+        {
+            continue;
+        }
+        let location = Location {
+            path: canonical,
+            line: location.line,
+        };
+        history.add(&location, mode, now);
+    }
+    history.age();
+    history.save(path)
+}
+
+/// Options for `builtin:history`
+#[derive(Debug, Default)]
+struct HistoryArgs {
+    /// Include files outside the current repository (or directory, if not in a repository)
+    all: bool,
+    /// Append `:<line>` to files opened at a line
+    lines: bool,
+    pattern: Option<Regex>,
+}
+
+impl HistoryArgs {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut parsed = Self::default();
+        for arg in args {
+            match arg.as_str() {
+                "--all" => parsed.all = true,
+                "--lines" => parsed.lines = true,
+                flag if flag.starts_with("--") => {
+                    return Err(eyre!(
+                        "Unknown option {flag} for builtin:history (expected --all, --lines)"
+                    ))
+                }
+                pattern if parsed.pattern.is_none() => {
+                    parsed.pattern = Some(
+                        Regex::new(pattern)
+                            .wrap_err_with(|| format!("Invalid pattern {pattern}"))?,
+                    )
+                }
+                _ => return Err(eyre!("builtin:history takes at most one pattern")),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// The paths to print for `builtin:history`, most frecent first. Paths under `cwd` are made
+/// relative to it.
+fn history_lines(history: &History, args: &HistoryArgs, cwd: &Path, now: u64) -> Vec<String> {
+    let vcs_root = path_util::vcs_root(cwd);
+    history
+        .ranked(now)
+        .into_iter()
+        .filter(|entry| {
+            args.all
+                || match vcs_root {
+                    Some(root) => entry.vcs_root.as_deref() == Some(root),
+                    None => entry.path.starts_with(cwd),
+                }
+        })
+        .filter(|entry| entry.path.exists())
+        .filter_map(|entry| {
+            let path = entry.path.strip_prefix(cwd).unwrap_or(&entry.path);
+            let path = path.to_str()?;
+            if args.pattern.as_ref().is_some_and(|re| !re.is_match(path)) {
+                return None;
+            }
+            Some(match (args.lines, entry.line) {
+                (true, Some(line)) => format!("{path}:{line}"),
+                _ => path.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// `builtin:history [--all] [--lines] [<regex>]`: prints files opened with voxide in the current
+/// repository (or under the current directory), most frecent first
+pub fn history_source(args: &[String]) -> Result<()> {
+    let args = HistoryArgs::parse(args)?;
+    let history = History::load(&history_path()?)?;
+    let cwd = std::env::current_dir()?.canonicalize()?;
+    for line in history_lines(&history, &args, &cwd, now()) {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_round_trip() {
+        let entry = Entry {
+            path: PathBuf::from("/repo/src/main.rs"),
+            line: Some(12),
+            vcs_root: Some(PathBuf::from("/repo")),
+            mode: "gz".to_owned(),
+            rank: 2.5,
+            last_access: 1700000000,
+        };
+        assert_eq!(Entry::parse(&entry.to_line()), Some(entry));
+        assert_eq!(
+            Entry::parse("1\t5\t\t\t\t/tmp/a b").map(|entry| entry.path),
+            Some(PathBuf::from("/tmp/a b"))
+        );
+        assert_eq!(Entry::parse("garbage"), None);
+    }
+
+    #[test]
+    fn test_history_frecency_and_filtering() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path().canonicalize()?;
+        let repo = dir.join("repo");
+        fs::create_dir_all(repo.join(".git"))?;
+        fs::create_dir_all(repo.join("src"))?;
+        for file in ["src/old.rs", "src/new.rs", "src/often.rs"] {
+            fs::write(repo.join(file), "")?;
+        }
+        fs::write(dir.join("outside.txt"), "")?;
+
+        let now = 10 * WEEK;
+        let location = |path: PathBuf, line| Location { path, line };
+        let mut history = History::default();
+        history.add(
+            &location(repo.join("src/old.rs"), None),
+            "g",
+            now - 2 * WEEK,
+        );
+        for _ in 0..3 {
+            history.add(
+                &location(repo.join("src/often.rs"), None),
+                "",
+                now - 2 * DAY,
+            );
+        }
+        history.add(&location(repo.join("src/new.rs"), Some(7)), "h", now);
+        history.add(&location(dir.join("outside.txt"), None), "", now);
+        history.add(&location(repo.join("src/gone.rs"), None), "", now);
+
+        let args = HistoryArgs::parse(&["--lines".to_owned()])?;
+        assert_eq!(
+            history_lines(&history, &args, &repo.join("src"), now),
+            vec!["new.rs:7", "often.rs", "old.rs"]
+        );
+
+        let args = HistoryArgs::parse(&["--all".to_owned(), "t".to_owned()])?;
+        let outside = dir.join("outside.txt").display().to_string();
+        assert_eq!(
+            history_lines(&history, &args, &repo, now),
+            vec![outside.as_str(), "src/often.rs"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_records() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path().canonicalize()?;
+        let path = dir.join("data/history");
+        std::thread::scope(|scope| {
+            let threads = (0..8)
+                .map(|i| {
+                    let file = dir.join(format!("{i}.txt"));
+                    let path = &path;
+                    scope.spawn(move || {
+                        fs::write(&file, "")?;
+                        let location = Location {
+                            path: file,
+                            line: None,
+                        };
+                        record_at(path, &[location], "", 0)
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .try_for_each(|thread| thread.join().expect("thread panicked"))
+        })?;
+        assert_eq!(History::load(&path)?.entries.len(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_aging() {
+        let mut history = History::default();
+        for i in 0..2 {
+            history.entries.push(Entry {
+                path: PathBuf::from(format!("/{i}")),
+                line: None,
+                vcs_root: None,
+                mode: String::new(),
+                rank: if i == 0 { MAX_TOTAL_RANK } else { 1.0 },
+                last_access: 0,
+            });
+        }
+        history.age();
+        assert_eq!(history.entries.len(), 1);
+        assert_eq!(history.entries[0].rank, MAX_TOTAL_RANK * AGING_FACTOR);
+    }
+}
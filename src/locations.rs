@@ -0,0 +1,243 @@
+use crate::config::get_config;
+use crate::path_util;
+use crate::transforms_replacement_preprocessor::expand_placeholders;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::path::{Component, Path, PathBuf};
+
+/// A named set of directories, listed by `builtin:locations <name>` like a script would
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct LocationSet {
+    /// Directories whose entries are listed. May start with `~/` or use ${gitroot}/${home}.
+    pub roots: Vec<String>,
+    /// How many levels below the roots to list (default 1: only their entries)
+    pub depth: Option<usize>,
+    /// List everything under the roots, however deep
+    #[serde(default)]
+    pub deep: bool,
+    /// Only list entries whose path (relative to its root) matches one of these globs
+    pub globs: Option<Vec<String>>,
+    /// gitignore-style patterns for entries to skip, along with hidden and ignored ones
+    pub ignore: Option<Vec<String>>,
+    /// Include hidden entries and those ignored by .gitignore
+    #[serde(default)]
+    pub hidden: bool,
+    /// Only list files or only directories
+    pub only: Option<EntryKind>,
+    /// When the query is the name of an entry in a root, print only that (default true)
+    pub exact: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Files,
+    Dirs,
+}
+
+impl LocationSet {
+    pub fn validate(&self, name: &str) -> Result<()> {
+        if self.roots.is_empty() {
+            return Err(eyre!("Location {name}: `roots` is empty"));
+        }
+        for glob in self.globs.iter().flatten() {
+            glob::Pattern::new(glob)
+                .wrap_err_with(|| format!("Location {name}: invalid glob {glob}"))?;
+        }
+        self.ignore_matcher(Path::new("/"))
+            .wrap_err_with(|| format!("Location {name}: invalid ignore pattern"))?;
+        Ok(())
+    }
+
+    fn ignore_matcher(&self, root: &Path) -> Result<Gitignore> {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in self.ignore.iter().flatten() {
+            builder.add_line(None, pattern)?;
+        }
+        Ok(builder.build()?)
+    }
+
+    fn roots(&self) -> Result<Vec<PathBuf>> {
+        self.roots
+            .iter()
+            .map(|root| {
+                let root = expand_placeholders(root);
+                Ok(match root.strip_prefix("~/") {
+                    Some(rest) => path_util::home_dir()?.join(rest),
+                    None => PathBuf::from(root),
+                })
+            })
+            .collect()
+    }
+
+    /// The entries in the roots with paths (relative to their root) containing `query`, or only
+    /// `query` itself if it's in a root and `exact` is on. Only a plain relative path can be an
+    /// exact match, so the query can't name anything outside the roots.
+    fn list(&self, query: &str) -> Result<Vec<PathBuf>> {
+        let roots = self.roots()?;
+        let is_relative_name = !query.is_empty()
+            && Path::new(query)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if self.exact.unwrap_or(true) && is_relative_name {
+            if let Some(exact) = roots
+                .iter()
+                .map(|root| root.join(query))
+                .find(|path| path.exists())
+            {
+                return Ok(vec![exact]);
+            }
+        }
+
+        let globs = self
+            .globs
+            .iter()
+            .flatten()
+            .map(|glob| glob::Pattern::new(glob))
+            .collect::<Result<Vec<_>, _>>()?;
+        let depth = if self.deep {
+            None
+        } else {
+            Some(self.depth.unwrap_or(1))
+        };
+        let mut entries = vec![];
+        for root in roots {
+            let ignore = self.ignore_matcher(&root)?;
+            let walk = WalkBuilder::new(&root)
+                .max_depth(depth)
+                .standard_filters(!self.hidden)
+                .sort_by_file_name(|a, b| a.cmp(b))
+                .filter_entry(move |entry| {
+                    let is_dir = entry.file_type().is_some_and(|kind| kind.is_dir());
+                    !ignore.matched(entry.path(), is_dir).is_ignore()
+                })
+                .build();
+            for entry in walk.filter_map(|entry| entry.ok()) {
+                let Ok(relative) = entry.path().strip_prefix(&root) else {
+                    continue;
+                };
+                let is_dir = entry.file_type().is_some_and(|kind| kind.is_dir());
+                let included = entry.depth() > 0
+                    && match self.only {
+                        Some(EntryKind::Files) => !is_dir,
+                        Some(EntryKind::Dirs) => is_dir,
+                        None => true,
+                    }
+                    && (globs.is_empty() || globs.iter().any(|glob| glob.matches_path(relative)))
+                    && relative.to_string_lossy().contains(query);
+                if included {
+                    entries.push(entry.into_path());
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// `builtin:locations <name> [<query>]`: prints the entries of the location set `name` from the
+/// config's `[locations]` whose paths contain `query`
+pub fn locations_source(args: &[String]) -> Result<()> {
+    let (name, query) = match args {
+        [name] => (name, ""),
+        [name, query] => (name, query.as_str()),
+        _ => return Err(eyre!("Usage: builtin:locations <name> [<query>]")),
+    };
+    let config = get_config()?;
+    let location = config.locations.get(name).ok_or_else(|| {
+        let mut names = config.locations.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        eyre!(
+            "No location {name} in the config (expected one of: {})",
+            names.join(", ")
+        )
+    })?;
+    for path in location.list(query)? {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use figment::{
+        providers::{Format, Toml},
+        Figment,
+    };
+    use std::fs;
+
+    fn location(toml: &str) -> LocationSet {
+        Figment::from(Toml::string(toml)).extract().unwrap()
+    }
+
+    #[test]
+    fn test_list() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        for file in [
+            "voxide/src/main.rs",
+            "voxide/README.md",
+            "zoxide/README.md",
+            "notes.md",
+            ".hidden/a.md",
+            "node_modules/x/y.md",
+        ] {
+            fs::create_dir_all(root.join(file).parent().unwrap())?;
+            fs::write(root.join(file), "")?;
+        }
+        let roots = format!("roots = [{:?}]", root.display().to_string());
+        let relative = |paths: Vec<PathBuf>| -> Vec<String> {
+            paths
+                .iter()
+                .map(|path| path.strip_prefix(root).unwrap().display().to_string())
+                .collect()
+        };
+
+        let dirs = location(&format!("{roots}\nonly = \"dirs\""));
+        assert_eq!(
+            relative(dirs.list("")?),
+            vec!["node_modules", "voxide", "zoxide"]
+        );
+        assert_eq!(relative(dirs.list("oxi")?), vec!["voxide", "zoxide"]);
+        // Exact name
+        assert_eq!(relative(dirs.list("voxide")?), vec!["voxide"]);
+        // ...but not outside the roots
+        let outside = root.join("voxide/src");
+        assert_eq!(dirs.list(&outside.display().to_string())?.len(), 0);
+        let sub = location(&format!("roots = [{:?}]", outside.display().to_string()));
+        assert_eq!(sub.list("../README.md")?.len(), 0);
+        assert_eq!(sub.list("./main.rs")?.len(), 0);
+        assert_eq!(relative(sub.list("main.rs")?), vec!["voxide/src/main.rs"]);
+
+        let deep = location(&format!(
+            "{roots}\ndeep = true\nglobs = [\"*.md\"]\nignore = [\"node_modules\"]"
+        ));
+        assert_eq!(
+            relative(deep.list("")?),
+            vec!["notes.md", "voxide/README.md", "zoxide/README.md"]
+        );
+        let depth = location(&format!(
+            "{roots}\ndepth = 2\nonly = \"files\"\nexact = false"
+        ));
+        assert_eq!(
+            relative(depth.list("README.md")?),
+            vec!["voxide/README.md", "zoxide/README.md"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(location("roots = []").validate("x").is_err());
+        assert!(location("roots = [\"/\"]\nglobs = [\"[\"]")
+            .validate("x")
+            .is_err());
+        assert!(location("roots = [\"/\"]\nignore = [\"*.o\"]")
+            .validate("x")
+            .is_ok());
+    }
+}
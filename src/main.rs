@@ -1,19 +1,43 @@
+mod builtins;
 mod command_wrapper;
+mod completions;
 mod config;
+mod config_finder;
+mod config_include;
+mod config_schema;
+mod content_search;
+mod file_acronym;
+mod file_walker;
+mod git_files;
+mod help;
+mod history;
 mod init_from_builtin_files;
+mod locations;
+mod mode_args;
+mod mode_merge;
 mod mode_script_runner;
+mod nvim_rpc;
+mod nvim_shada;
 mod path_util;
+mod project_config;
+mod quickfix;
+mod shell_init;
 mod transforms_applicator;
 mod transforms_replacement_preprocessor;
 
 use crate::command_wrapper::CommandWrapper;
+use crate::history::Location;
 use crate::mode_script_runner::ModeScriptRunner;
 use crate::transforms_applicator::TransformsApplicator;
+use crate::transforms_replacement_preprocessor::expand_placeholders;
 use color_eyre::eyre::{OptionExt, Result, WrapErr};
 use config::get_config;
 use log::debug;
-use std::cmp::Ordering;
+use path_util::config_paths_env_value;
+use std::os::fd::AsFd;
+use std::os::unix::fs::FileTypeExt;
 use std::path::Path;
+use std::path::PathBuf;
 
 static README: &str = include_str!("../README.md");
 
@@ -27,19 +51,19 @@ static README: &str = include_str!("../README.md");
 // - [ ] use Script for command too
 // TODO AFTER RELEASE:
 // - [ ] line numbers -- what to do if multipl files? auto make into quickfix?
-// - [ ] tabry configs inside voxide config
 // - [ ] todos, tests
 // - [ ] inline scripts?
 // - [ ] "locations" see todo?
 // - [ ] transforms in modes? "config" could be implemented via
 
 /// Add arguments (corresponding to files to open) to the Command, applying the transforms (as
-/// defined in the config) if necessary.
+/// defined in the config) if necessary. Returns the transformed locations, for the history.
 fn add_file_args<'a>(
     output: &mut CommandWrapper,
     iter: impl Iterator<Item = &'a Path>,
     ta: Option<TransformsApplicator>,
-) -> Result<()> {
+) -> Result<Vec<Location>> {
+    let mut locations = vec![];
     if let Some(ta) = ta {
         for arg in iter {
             if let Some(string_arg) = arg.to_str() {
@@ -47,9 +71,13 @@ fn add_file_args<'a>(
                 output.arg(arg.as_ref());
                 // TODO: make this configurable
                 // TODO: doesn't work in vim if multiple files
-                if let Some(lineno) = lineno {
+                if let Some(lineno) = &lineno {
                     output.arg(format!("+{}", lineno.0));
                 }
+                locations.push(Location {
+                    path: PathBuf::from(arg.as_ref()),
+                    line: lineno.map(|lineno| lineno.0),
+                });
             } else {
                 // stderr println warning:
                 eprintln!(
@@ -63,86 +91,164 @@ fn add_file_args<'a>(
         output.args(iter);
     }
 
-    Ok(())
+    Ok(locations)
 }
 
 fn run_editor<'a>(
+    mode: &config::Mode,
     cmd: &str,
     iter: impl Iterator<Item = &'a Path>,
     ta: Option<TransformsApplicator>,
-) -> Result<()> {
+) -> Result<Vec<Location>> {
     // TODO real shell split, or just allokw array like script
     let mut cmd_iter = cmd.split_whitespace();
     let actual_cmd = cmd_iter.next().ok_or_eyre("Empty cmd")?;
     let mut output = CommandWrapper::new_from_script_path(actual_cmd)?;
-    output.args(cmd_iter);
+    output.args(cmd_iter).mode_env(mode);
+    // builtin:nvim would take a file starting with `-` for an option. (Not for a quickfix_cmd,
+    // which ends with `-q` for the quickfix file.)
+    if builtins::builtin_name(actual_cmd) == Some("nvim") && !mode.quickfix.unwrap_or(false) {
+        output.arg("--");
+    }
 
-    add_file_args(&mut output, iter, ta)?;
+    let locations = add_file_args(&mut output, iter, ta)?;
 
     debug!("Running editor: {:?}", output);
-    output.run()
+    output.run()?;
+    Ok(locations)
 }
 
-fn help() -> Result<()> {
-    let config_res = get_config();
-    println!("voxide -- flexible, customizable file finder/opener");
-    println!("voxide finds files and line numbers and opens them (via arguments to your");
-    println!("editor or a quickfix file) using scripts/commands/transforms defined in the");
-    println!("configuration file.");
-    println!();
-    println!("All invocations begin with the default (base) mode defined in the config file under");
-    println!("`default_mode`. Any further modes (letters) in the first argument merge (layer) on");
-    println!("top of this to determine the final mode's cmd, script, etc.");
-
-    println!();
-    println!("Run 'voxide --init' to initialize with a default config and scripts.");
-    println!("Run 'voxide --readme' to print out the README.md file compiled into voxide.");
-    println!("Run RUST_LOG=debug voxide... to show debugging info.");
-    println!();
-    println!("Usage: voxide [<mode letter(s)>] [<arguments to mode scripts> ...]");
-    println!();
-    match config_res {
-        Ok(config) => {
-            println!("Available modes:");
-            let mut modes = config.modes.iter().collect::<Vec<(&char, &config::Mode)>>();
-            modes.sort_by(|kv1, kv2| kv1.0.partial_cmp(kv2.0).unwrap_or(Ordering::Equal));
-            for (k, v) in &modes {
-                println!("{}:  {}", k, v.name);
-                println!("    {}", v.desc);
-            }
-        }
-        Err(_) => {
-            println!("No valid config available; run with no arguments to get full error or run with --init to initialize with default config and scripts, or --help for more info.");
-        }
+/// Export information about this invocation to scripts, filters and cmds as VOXIDE_* environment
+/// variables. (VOXIDE_OUTPUT_FILE is set by ModeScriptRunner for modes using a tempfile.)
+fn export_context_env(letters: Option<&str>, mode: &config::Mode) -> Result<()> {
+    let quickfix = if mode.quickfix.unwrap_or(false) {
+        "1"
+    } else {
+        "0"
+    };
+    let vars = [
+        ("VOXIDE_MODE_LETTERS", letters.unwrap_or("").to_owned()),
+        ("VOXIDE_MODE_NAME", mode.name.clone()),
+        ("VOXIDE_QUICKFIX", quickfix.to_owned()),
+        ("VOXIDE_CONFIG_PATHS", config_paths_env_value()?),
+        ("VOXIDE_GITROOT", expand_placeholders("${gitroot}")),
+        (
+            "VOXIDE_CALLER_CWD",
+            std::env::current_dir()?.display().to_string(),
+        ),
+    ];
+    for (key, value) in vars {
+        debug!("Exporting {key}={value}");
+        std::env::set_var(key, value);
     }
-
     Ok(())
 }
 
+/// Whether stdin is a pipe or a file, so `stdin_mode` applies. Not when it's a terminal, nor e.g.
+/// /dev/null or closed, as when run from a GUI launcher or cron.
+fn stdin_is_piped() -> bool {
+    let Ok(stdin) = std::io::stdin().as_fd().try_clone_to_owned() else {
+        return false;
+    };
+    std::fs::File::from(stdin)
+        .metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_fifo() || metadata.is_file())
+}
+
 fn main() -> Result<()> {
     color_eyre::install()?;
     env_logger::init();
 
+    project_config::take_inherited_project_configs();
+
     let mut args = std::env::args().skip(1);
-    let first_arg = args.next();
+    let mut first_arg = args.next();
+
+    let mut config_paths = vec![];
+    while first_arg.as_deref() == Some("--config") {
+        let path = args.next().ok_or_eyre("--config requires a path")?;
+        config_paths.push(PathBuf::from(path));
+        first_arg = args.next();
+    }
+    if !config_paths.is_empty() {
+        path_util::set_config_paths(config_paths);
+    }
+
     match first_arg.as_deref() {
-        Some("--help") => return help(),
+        Some("--help") => return help::help(args.next().as_deref()),
         Some("--init") => return init_from_builtin_files::init(),
+        Some("--trust") => return project_config::trust_project_configs(),
+        Some("--print-schema") => return config_schema::print_schema(),
+        Some("--check-config") => return config_schema::check_config(),
+        Some("--config-sources") => return config::print_config_sources(),
+        Some("--builtin") => {
+            let name = args
+                .next()
+                .ok_or_eyre("--builtin requires a builtin name")?;
+            return builtins::run_builtin(&name, &args.collect::<Vec<_>>());
+        }
+        Some("--complete") => return completions::complete(&args.collect::<Vec<_>>()),
+        Some("--completions") => {
+            let shell = args
+                .next()
+                .ok_or_eyre("--completions requires a shell (bash, zsh, fish or tabry)")?;
+            let name = args.next().unwrap_or_else(|| "voxide".to_owned());
+            return completions::print_completions(&shell, &name);
+        }
+        Some("--init-shell") => {
+            let shell = args
+                .next()
+                .ok_or_eyre("--init-shell requires a shell (bash, zsh or fish)")?;
+            let name = args.next().unwrap_or_else(|| "v".to_owned());
+            return shell_init::print_init_shell(&shell, &name);
+        }
         Some("--readme") => {
             println!("{}", README);
             return Ok(());
         }
+        Some("--mode") => {
+            let name = args
+                .next()
+                .ok_or_eyre("--mode requires a mode or alias name")?;
+            first_arg = Some(format!(":{name}"));
+        }
         _ => (),
     };
 
+    // Read (and don't pass on to scripts) where to write a directory result for the shell to cd to
+    let cd_file = std::env::var_os(shell_init::CD_FILE_ENV_VAR).map(PathBuf::from);
+    std::env::remove_var(shell_init::CD_FILE_ENV_VAR);
+
     let config = get_config().wrap_err(
         "Invalid/missing config! Run with --init to install default config and scripts",
     )?;
-    let mode = config.get_merged_mode(first_arg.as_deref())?;
+    let stdin_is_piped = stdin_is_piped();
+    let letters = config.letters_with_stdin_mode(first_arg.as_deref(), stdin_is_piped);
+    let mut mode =
+        config.get_merged_mode(letters.as_deref(), config.uses_stdin_mode(stdin_is_piped))?;
+    mode.resolve_cwd()?;
+    export_context_env(letters.as_deref(), &mode)?;
     let mode_script_runner = ModeScriptRunner::new(&mode, args)?;
+    if let Some(cwd) = &mode.cwd {
+        // Script output is relative to the mode's cwd, so transforms should be too
+        std::env::set_current_dir(cwd)
+            .wrap_err_with(|| format!("Failed to change to mode's cwd {cwd}"))?;
+    }
 
     let mut files_iter = mode_script_runner.files_iter().peekable();
-    let first_is_dir = files_iter.peek().map_or(false, |x| Path::new(x).is_dir());
+    let first_is_dir = files_iter.peek().is_some_and(|x| Path::new(x).is_dir());
+    if let (true, Some(cd_file)) = (first_is_dir, &cd_file) {
+        let dir = files_iter.next().map(Path::canonicalize).transpose()?;
+        if let Some(dir) = dir {
+            debug!(
+                "Writing directory {} to {}",
+                dir.display(),
+                cd_file.display()
+            );
+            std::fs::write(cd_file, dir.as_os_str().as_encoded_bytes())?;
+        }
+        return Ok(());
+    }
     let is_quickfix = mode.quickfix.unwrap_or(false);
     let ta = if is_quickfix {
         None
@@ -152,7 +258,12 @@ fn main() -> Result<()> {
 
     let cmd = mode.cmd_for_isdir_and_qf(first_is_dir, is_quickfix)?;
 
-    run_editor(cmd, files_iter, ta)?;
+    let locations = run_editor(&mode, cmd, files_iter, ta)?;
+    if config.record_history && !locations.is_empty() {
+        if let Err(err) = history::record(&locations, letters.as_deref().unwrap_or("")) {
+            eprintln!("Warning: failed to record history: {err}");
+        }
+    }
 
     Ok(())
 }
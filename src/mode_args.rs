@@ -0,0 +1,281 @@
+use crate::config::Mode;
+use color_eyre::eyre::{eyre, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// Declares one of the arguments a mode's script takes from the command line (after the mode
+/// letters). Declared arguments are checked before the script runs, passed to it in argv (with
+/// defaults filled in) and exported as `VOXIDE_ARG_<NAME>`.
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct ArgSpec {
+    pub name: String,
+    pub desc: Option<String>,
+    /// Value used when the argument isn't given
+    pub default: Option<String>,
+    /// Fail if the argument isn't given
+    #[serde(default)]
+    pub required: bool,
+    /// The only values allowed
+    pub choices: Option<Vec<String>>,
+    /// Takes all remaining arguments (only valid for the last argument)
+    #[serde(default)]
+    pub rest: bool,
+}
+
+impl ArgSpec {
+    /// Name of the environment variable the argument is exported as, e.g. `VOXIDE_ARG_GIT_REF`
+    pub fn env_var(&self) -> String {
+        let name = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        format!("VOXIDE_ARG_{name}")
+    }
+
+    /// How the argument is shown in usage, e.g. `<ref>`, `[ref]` or `[paths...]`
+    pub fn usage(&self) -> String {
+        let dots = if self.rest { "..." } else { "" };
+        if self.required {
+            format!("<{}{dots}>", self.name)
+        } else {
+            format!("[{}{dots}]", self.name)
+        }
+    }
+
+    /// One-line description for help, e.g. `git-ref: commit to show (default: HEAD)`
+    pub fn describe(&self) -> String {
+        let mut details = vec![];
+        if let Some(default) = &self.default {
+            details.push(format!("default: {default}"));
+        }
+        if let Some(choices) = &self.choices {
+            details.push(format!("one of: {}", choices.join(", ")));
+        }
+        let mut line = self.name.clone();
+        if let Some(desc) = &self.desc {
+            line.push_str(&format!(": {desc}"));
+        }
+        if !details.is_empty() {
+            line.push_str(&format!(" ({})", details.join("; ")));
+        }
+        line
+    }
+
+    fn check_choice(&self, mode: &Mode, value: &str) -> Result<()> {
+        match &self.choices {
+            Some(choices) if !choices.iter().any(|choice| choice == value) => Err(eyre!(
+                "Mode {}: invalid value `{value}` for {} (expected one of: {})",
+                mode.name,
+                self.usage(),
+                choices.join(", ")
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Usage line for a mode's declared arguments, e.g. `<ref> [paths...]`
+pub fn usage(specs: &[ArgSpec]) -> String {
+    specs
+        .iter()
+        .map(ArgSpec::usage)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Checks that a mode's `arg_spec` makes sense (when the config is loaded)
+pub fn validate_arg_spec(mode_name: &str, specs: &[ArgSpec]) -> Result<()> {
+    for (i, spec) in specs.iter().enumerate() {
+        if spec.rest && i + 1 != specs.len() {
+            return Err(eyre!(
+                "Mode {mode_name}: only the last argument can be `rest` (not {})",
+                spec.name
+            ));
+        }
+        if spec.required && spec.default.is_some() {
+            return Err(eyre!(
+                "Mode {mode_name}: argument {} is required so can't have a default",
+                spec.name
+            ));
+        }
+        if let (Some(default), Some(choices)) = (&spec.default, &spec.choices) {
+            if !choices.contains(default) {
+                return Err(eyre!(
+                    "Mode {mode_name}: default `{default}` for argument {} is not one of its choices",
+                    spec.name
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Command-line arguments checked against a mode's `arg_spec`
+#[derive(Debug, PartialEq)]
+pub struct ResolvedArgs {
+    /// Arguments for the script, with defaults filled in up to the first missing argument
+    pub argv: Vec<String>,
+    /// `VOXIDE_ARG_*` variables for each argument with a value (`rest` values joined by newlines)
+    pub env: Vec<(String, String)>,
+}
+
+/// Checks `argv` against the mode's `arg_spec` (if it has one, otherwise `argv` is passed through
+/// as-is), filling in defaults.
+pub fn resolve_args(mode: &Mode, argv: Vec<String>) -> Result<ResolvedArgs> {
+    let Some(specs) = &mode.arg_spec else {
+        return Ok(ResolvedArgs { argv, env: vec![] });
+    };
+
+    let mut given = argv.into_iter();
+    let mut resolved = ResolvedArgs {
+        argv: vec![],
+        env: vec![],
+    };
+    let mut missing = false;
+    for spec in specs {
+        let values = if spec.rest {
+            given.by_ref().collect::<Vec<_>>()
+        } else {
+            given.next().into_iter().collect()
+        };
+        let values = match (values.is_empty(), &spec.default) {
+            (false, _) => values,
+            (true, Some(default)) => vec![default.clone()],
+            (true, None) if spec.required => {
+                return Err(eyre!(
+                    "Mode {}: missing required argument {} (usage: {})",
+                    mode.name,
+                    spec.usage(),
+                    usage(specs)
+                ));
+            }
+            (true, None) => {
+                // Later arguments can't be passed positionally, but still go in the env
+                missing = true;
+                continue;
+            }
+        };
+        for value in &values {
+            spec.check_choice(mode, value)?;
+        }
+        resolved.env.push((spec.env_var(), values.join("\n")));
+        if !missing {
+            resolved.argv.extend(values);
+        }
+    }
+
+    let extra = given.collect::<Vec<_>>();
+    if !extra.is_empty() {
+        return Err(eyre!(
+            "Mode {}: too many arguments: {} (usage: {})",
+            mode.name,
+            extra.join(" "),
+            usage(specs)
+        ));
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_resolve_args() -> Result<()> {
+        let config = test_config(
+            r#"
+            [modes.g]
+            arg_spec = [
+              { name = "kind", required = true, choices = ["staged", "commit"] },
+              { name = "git-ref", default = "HEAD" },
+              { name = "paths", rest = true },
+            ]
+            "#,
+        )?;
+        let mode = config.get_mode("g")?;
+        assert_eq!(
+            resolve_args(mode, args(&["commit"]))?,
+            ResolvedArgs {
+                argv: args(&["commit", "HEAD"]),
+                env: vec![
+                    ("VOXIDE_ARG_KIND".to_owned(), "commit".to_owned()),
+                    ("VOXIDE_ARG_GIT_REF".to_owned(), "HEAD".to_owned()),
+                ],
+            }
+        );
+        assert_eq!(
+            resolve_args(mode, args(&["staged", "@^", "a", "b"]))?.argv,
+            args(&["staged", "@^", "a", "b"])
+        );
+
+        let err = |argv: &[&str]| resolve_args(mode, args(argv)).unwrap_err().to_string();
+        assert_eq!(
+            err(&[]),
+            "Mode git: missing required argument <kind> (usage: <kind> [git-ref] [paths...])"
+        );
+        assert_eq!(
+            err(&["other"]),
+            "Mode git: invalid value `other` for <kind> (expected one of: staged, commit)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_args_missing_optional() -> Result<()> {
+        let config = test_config(
+            r#"
+            [modes.g]
+            arg_spec = [{ name = "dir" }, { name = "depth", default = "1" }]
+            "#,
+        )?;
+        let mode = config.get_mode("g")?;
+        assert_eq!(
+            resolve_args(mode, vec![])?,
+            ResolvedArgs {
+                argv: vec![],
+                env: vec![("VOXIDE_ARG_DEPTH".to_owned(), "1".to_owned())],
+            }
+        );
+        assert_eq!(
+            resolve_args(mode, args(&["a", "2", "3"]))
+                .unwrap_err()
+                .to_string(),
+            "Mode git: too many arguments: 3 (usage: [dir] [depth])"
+        );
+        // Without an arg_spec, arguments are passed through unchecked
+        assert_eq!(
+            resolve_args(test_config("")?.get_mode("g")?, args(&["a", "b"]))?.argv,
+            args(&["a", "b"])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_arg_spec() {
+        // Arg specs are validated when the config is loaded
+        let err = test_config(
+            r#"
+            [modes.g]
+            arg_spec = [{ name = "paths", rest = true }, { name = "dir" }]
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Mode g: only the last argument can be `rest` (not paths)"
+        );
+    }
+}
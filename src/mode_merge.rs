@@ -0,0 +1,226 @@
+use crate::config::{Mode, PriorFilter};
+use color_eyre::eyre::{eyre, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// How a field is combined when two modes (letters) that both set it are merged. Modes are merged
+/// in the order given, after the default mode.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeStrategy {
+    /// The last mode that sets the field wins
+    Override,
+    /// The first mode (other than the default mode) that sets the field wins
+    FirstWins,
+    /// Values are combined: filters are chained, args concatenated, env tables merged (later keys
+    /// win). Only valid for `filter`, `args` and `env`.
+    Append,
+}
+
+use MergeStrategy::*;
+
+/// Per-field merge strategies, from the `[merge]` table in the config. `quickfix`,
+/// `script_uses_tempfile`, `timeout`, `arg_spec` and `complete` always travel with `script`, as do
+/// `args` unless they're appended, and `filter_timeout` travels with `filter`.
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+#[serde(default)]
+#[schemars(deny_unknown_fields)]
+pub struct MergeConfig {
+    pub script: MergeStrategy,
+    pub filter: MergeStrategy,
+    pub cmd: MergeStrategy,
+    pub dir_cmd: MergeStrategy,
+    pub quickfix_cmd: MergeStrategy,
+    pub cwd: MergeStrategy,
+    pub args: MergeStrategy,
+    pub env: MergeStrategy,
+}
+
+impl Default for MergeConfig {
+    fn default() -> Self {
+        Self {
+            script: Override,
+            filter: Override,
+            cmd: Override,
+            dir_cmd: Override,
+            quickfix_cmd: Override,
+            cwd: Override,
+            args: Override,
+            env: Append,
+        }
+    }
+}
+
+impl MergeConfig {
+    pub fn validate(&self) -> Result<()> {
+        let fields = [
+            ("script", self.script),
+            ("cmd", self.cmd),
+            ("dir_cmd", self.dir_cmd),
+            ("quickfix_cmd", self.quickfix_cmd),
+            ("cwd", self.cwd),
+        ];
+        match fields.iter().find(|(_, strategy)| *strategy == Append) {
+            Some((field, _)) => Err(eyre!(
+                "merge.{field} cannot be \"append\" (only filter, args and env can)"
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Strategies for merging modes onto a fallback (the default mode, or a mode being extended)
+    pub fn onto_fallback(&self) -> Self {
+        let no_first_wins = |strategy| match strategy {
+            FirstWins => Override,
+            other => other,
+        };
+        Self {
+            script: no_first_wins(self.script),
+            filter: no_first_wins(self.filter),
+            cmd: no_first_wins(self.cmd),
+            dir_cmd: no_first_wins(self.dir_cmd),
+            quickfix_cmd: no_first_wins(self.quickfix_cmd),
+            cwd: no_first_wins(self.cwd),
+            args: no_first_wins(self.args),
+            env: no_first_wins(self.env),
+        }
+    }
+}
+
+/// Whether a field set in both modes should take the later mode's value
+fn later_wins<T>(strategy: MergeStrategy, this: &Option<T>, later: &Option<T>) -> bool {
+    match (this, later) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(_), Some(_)) => strategy != FirstWins,
+    }
+}
+
+fn merge_field<T: Clone>(strategy: MergeStrategy, this: &mut Option<T>, later: &Option<T>) {
+    if later_wins(strategy, this, later) {
+        this.clone_from(later);
+    }
+}
+
+impl Mode {
+    /// Merge `later` into self according to the strategies in `merge`. Does not touch `name`,
+    /// `desc`, `category`, `examples` or `extends`.
+    pub fn merge(&mut self, later: &Mode, merge: &MergeConfig) {
+        if later_wins(merge.script, &self.script, &later.script) {
+            self.script.clone_from(&later.script);
+            self.quickfix = later.quickfix;
+            self.script_uses_tempfile = later.script_uses_tempfile;
+            self.timeout = later.timeout;
+            self.arg_spec.clone_from(&later.arg_spec);
+            self.complete.clone_from(&later.complete);
+            if merge.args != Append {
+                self.args.clone_from(&later.args);
+            }
+        }
+
+        match (merge.filter, &self.filter, &later.filter) {
+            (Append, Some(filter), Some(_)) => {
+                self.prior_filters.push(PriorFilter {
+                    script: filter.clone(),
+                    timeout: self.filter_timeout,
+                });
+                self.prior_filters
+                    .extend(later.prior_filters.iter().cloned());
+                self.filter.clone_from(&later.filter);
+                self.filter_timeout = later.filter_timeout;
+            }
+            (strategy, _, _) => {
+                if later_wins(strategy, &self.filter, &later.filter) {
+                    self.prior_filters.clone_from(&later.prior_filters);
+                    self.filter.clone_from(&later.filter);
+                    self.filter_timeout = later.filter_timeout;
+                }
+            }
+        }
+
+        match (merge.args, &mut self.args, &later.args) {
+            (Append, Some(args), Some(later_args)) => args.extend(later_args.iter().cloned()),
+            (Append, args, later_args) => merge_field(Append, args, later_args),
+            // Args without a script are for whichever script is used (e.g. one from `extends`)
+            (strategy, args, later_args) if later.script.is_none() => {
+                merge_field(strategy, args, later_args)
+            }
+            _ => (),
+        }
+
+        match (merge.env, &mut self.env, &later.env) {
+            (Append, Some(env), Some(later_env)) => env.extend(later_env.clone()),
+            (strategy, env, later_env) => merge_field(strategy, env, later_env),
+        }
+
+        merge_field(merge.cmd, &mut self.cmd, &later.cmd);
+        merge_field(merge.dir_cmd, &mut self.dir_cmd, &later.dir_cmd);
+        merge_field(
+            merge.quickfix_cmd,
+            &mut self.quickfix_cmd,
+            &later.quickfix_cmd,
+        );
+        merge_field(merge.cwd, &mut self.cwd, &later.cwd);
+    }
+}
+
+/// Result of merging the modes given on the command line onto the default mode
+pub struct MergedMode {
+    pub mode: Mode,
+    /// Descriptions of fields that more than one mode set, where one silently lost
+    pub conflicts: Vec<String>,
+}
+
+/// Merges `modes` in order, then merges the result onto `default`. The merged mode's name and desc
+/// are the last mode's (or the default mode's if there are none).
+/// `overridable` modes (e.g. from `stdin_mode`) are expected to be overridden, so don't count as
+/// conflicts.
+pub fn merge_modes(
+    default: &Mode,
+    modes: &[&Mode],
+    overridable: &[&Mode],
+    merge: &MergeConfig,
+) -> MergedMode {
+    let mut conflicts = vec![];
+    let mut script_from: Option<&Mode> = None;
+    let mut merged: Option<Mode> = None;
+
+    for &mode in modes {
+        if mode.script.is_some() {
+            match script_from {
+                Some(prev) if !overridable.iter().any(|o| std::ptr::eq(*o, prev)) => {
+                    let winner = if merge.script == FirstWins {
+                        prev
+                    } else {
+                        mode
+                    };
+                    conflicts.push(format!(
+                        "modes \"{}\" and \"{}\" both set a script; using \"{}\"'s",
+                        prev.name, mode.name, winner.name
+                    ));
+                }
+                _ => (),
+            }
+            if script_from.is_none() || merge.script == Override {
+                script_from = Some(mode);
+            }
+        }
+
+        match &mut merged {
+            None => merged = Some(mode.clone()),
+            Some(merged) => merged.merge(mode, merge),
+        }
+    }
+
+    let mut result = default.clone();
+    if let (Some(merged), Some(last)) = (merged, modes.last()) {
+        result.merge(&merged, &merge.onto_fallback());
+        result.name.clone_from(&last.name);
+        result.desc.clone_from(&last.desc);
+    }
+
+    MergedMode {
+        mode: result,
+        conflicts,
+    }
+}
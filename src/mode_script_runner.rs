@@ -1,8 +1,10 @@
 use crate::command_wrapper::CommandWrapper;
 use crate::config::Mode;
+use crate::mode_args::resolve_args;
+use crate::project_config::{project_configs_env_value, PROJECT_CONFIGS_ENV_VAR};
 use color_eyre::eyre::{eyre, Result};
 use log::debug;
-use std::{iter, path::Path};
+use std::{iter, path::Path, time::Duration};
 use tempfile::NamedTempFile;
 
 /// Runs script defined in a mode and returns the files to be opened (or a quickfix file)
@@ -24,15 +26,25 @@ impl ModeScriptRunner {
             .ok_or_else(|| eyre!("No script found for mode {:?}", mode))?;
         let uses_tempfile = mode.script_uses_tempfile.unwrap_or(false);
         let quickfix = mode.quickfix.unwrap_or(false);
+        let resolved = resolve_args(mode, argv.collect())?;
+        // Mode args come before any given on the command line
+        let argv = mode.args.iter().flatten().cloned().chain(resolved.argv);
 
         let mut command = CommandWrapper::new(script)?;
+        command
+            .timeout("script", mode.timeout.map(Duration::from_secs))
+            .mode_env(mode)
+            .pipeline_env(PROJECT_CONFIGS_ENV_VAR, project_configs_env_value()?);
+        for (key, value) in &resolved.env {
+            command.env(key, value);
+        }
         debug!("uses_tempfile={uses_tempfile}, quickfix={quickfix}");
         let res = match (uses_tempfile, quickfix) {
             (false, true) => {
                 let tempfile = NamedTempFile::new()?;
                 command.args(argv);
                 command
-                    .thru_filter(&mode.filter)?
+                    .thru_filter(mode)?
                     .stdout_to_tempfile(&tempfile)?
                     .run()?;
                 ModeScriptRunner::Quickfix {
@@ -42,8 +54,7 @@ impl ModeScriptRunner {
             (false, false) => {
                 // get output and split by newline and use that as filenames to pass to editor
                 command.args(argv);
-                let newline_separated_files =
-                    command.thru_filter(&mode.filter)?.run_get_output()?;
+                let newline_separated_files = command.thru_filter(mode)?.run_get_output()?;
                 ModeScriptRunner::FilesList {
                     newline_separated_files,
                 }
@@ -53,6 +64,7 @@ impl ModeScriptRunner {
                 // editor
                 let tempfile = NamedTempFile::new()?;
                 command.arg(tempfile.path());
+                command.env("VOXIDE_OUTPUT_FILE", tempfile.path());
                 command.args(argv);
                 command.run()?;
                 let newline_separated_files = std::fs::read_to_string(tempfile.path())?;
@@ -65,6 +77,7 @@ impl ModeScriptRunner {
                 // file
                 let tempfile = NamedTempFile::new()?;
                 command.arg(tempfile.path());
+                command.env("VOXIDE_OUTPUT_FILE", tempfile.path());
                 command.args(argv);
                 command.run()?;
                 ModeScriptRunner::Quickfix {
@@ -102,6 +115,8 @@ mod tests {
         Mode {
             name: "foo".to_string(),
             desc: "foo".to_string(),
+            category: None,
+            examples: None,
             script,
             cmd: Some("nvim".to_string()),
             quickfix_cmd: Some("nvim -q".to_string()),
@@ -109,6 +124,15 @@ mod tests {
             script_uses_tempfile: Some(false),
             quickfix: Some(false),
             filter: None,
+            timeout: None,
+            filter_timeout: None,
+            env: None,
+            cwd: None,
+            extends: None,
+            args: None,
+            arg_spec: None,
+            complete: None,
+            prior_filters: vec![],
         }
     }
 
@@ -160,6 +184,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_script_tempfile_from_env() -> Result<()> {
+        let mode = mkmode(&["sh", "-c", "echo $1 > $VOXIDE_OUTPUT_FILE"]);
+        let mode = Mode {
+            script_uses_tempfile: Some(true),
+            ..mode
+        };
+        let msr = ModeScriptRunner::new(&mode, vec!["hello".to_owned()].into_iter())?;
+        let files = msr
+            .files_iter()
+            .map(|x| x.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec!["hello"]);
+        Ok(())
+    }
+
     #[test]
     fn test_script_tempfile_to_quickfix() -> Result<()> {
         let mode = mkmode(&["bash", "-c", "echo $1 > $0"]);
@@ -180,4 +220,91 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_script_timeout() {
+        let mode = mkmode(&["sh", "-c", "sleep 5; echo $0"]);
+        let mode = Mode {
+            timeout: Some(1),
+            ..mode
+        };
+        let start = std::time::Instant::now();
+        let err = ModeScriptRunner::new(&mode, vec!["ok".to_owned()].into_iter())
+            .err()
+            .expect("script should time out");
+        assert_eq!(err.to_string(), "script timed out after 1s");
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_filter_timeout() {
+        let mode = mkmode(&["bash", "-c", "echo $0"]);
+        let mode = Mode {
+            filter: Some(Script::CommandWithArgs(vec![
+                "sh".to_owned(),
+                "-c".to_owned(),
+                "sleep 5; cat".to_owned(),
+            ])),
+            filter_timeout: Some(1),
+            ..mode
+        };
+        let err = ModeScriptRunner::new(&mode, vec!["ok".to_owned()].into_iter())
+            .err()
+            .expect("filter should time out");
+        assert_eq!(err.to_string(), "filter timed out after 1s");
+    }
+
+    #[test]
+    fn test_timed_filter_stays_in_process_group() -> Result<()> {
+        // So it can use the terminal (e.g. fzf) instead of being stopped by SIGTTIN/SIGTTOU
+        let mode = mkmode(&["sh", "-c", "echo $0"]);
+        let mode = Mode {
+            filter: Some(Script::CommandWithArgs(vec![
+                "sh".to_owned(),
+                "-c".to_owned(),
+                "cat >/dev/null; ps -o pgid= -p $$".to_owned(),
+            ])),
+            filter_timeout: Some(5),
+            ..mode
+        };
+        let runner = ModeScriptRunner::new(&mode, vec!["ok".to_owned()].into_iter())?;
+        let pgid = runner.files_iter().next().unwrap().to_str().unwrap().trim();
+        // SAFETY: getpgrp has no memory safety requirements
+        assert_eq!(pgid, unsafe { libc::getpgrp() }.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_script_env_and_cwd() -> Result<()> {
+        let mode = mkmode(&["sh", "-c", "echo $PWD/$VOXIDE_TEST_FILE"]);
+        let dir = tempfile::tempdir()?;
+        let cwd = dir.path().canonicalize()?;
+        let mode = Mode {
+            env: Some([("VOXIDE_TEST_FILE".to_owned(), "foo.txt".to_owned())].into()),
+            cwd: Some(cwd.to_str().unwrap().to_owned()),
+            ..mode
+        };
+        let msr = ModeScriptRunner::new(&mode, iter::empty())?;
+        let files = msr.files_iter().collect::<Vec<_>>();
+        assert_eq!(files, vec![cwd.join("foo.txt")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_script_relative_cwd() -> Result<()> {
+        let mut mode = Mode {
+            cwd: Some("src".to_owned()),
+            ..mkmode(&["sh", "-c", "echo $PWD/main.rs"])
+        };
+        mode.resolve_cwd()?;
+        let src = std::env::current_dir()?.join("src");
+        assert_eq!(mode.cwd.as_deref(), src.to_str());
+        let msr = ModeScriptRunner::new(&mode, iter::empty())?;
+        let files = msr.files_iter().collect::<Vec<_>>();
+        assert_eq!(files, vec![src.join("main.rs")]);
+        // Resolving again (e.g. from inside the cwd) leaves it alone
+        mode.resolve_cwd()?;
+        assert_eq!(mode.cwd.as_deref(), src.to_str());
+        Ok(())
+    }
 }
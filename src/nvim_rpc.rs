@@ -0,0 +1,532 @@
+use crate::command_wrapper::CommandWrapper;
+use crate::quickfix::QuickfixEntry;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use log::debug;
+use rmpv::Value;
+use std::fs;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// msgpack-RPC message types (see `:help msgpack-rpc`)
+const REQUEST: u64 = 0;
+const RESPONSE: u64 = 1;
+/// How long to wait for Neovim to answer a request
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A minimal, synchronous msgpack-RPC client for a Neovim server
+struct NvimClient {
+    reader: BufReader<Box<dyn Read>>,
+    writer: BufWriter<Box<dyn Write>>,
+    next_id: u64,
+}
+
+impl NvimClient {
+    /// Connects to `address`: a Unix socket path, or `host:port` for TCP
+    fn connect(address: &str) -> Result<Self> {
+        let connect_error = || format!("Failed to connect to Neovim at {address}");
+        let (reader, writer): (Box<dyn Read>, Box<dyn Write>) =
+            if address.contains('/') || !address.contains(':') {
+                let stream = UnixStream::connect(address).wrap_err_with(connect_error)?;
+                stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+                (Box::new(stream.try_clone()?), Box::new(stream))
+            } else {
+                let stream = TcpStream::connect(address).wrap_err_with(connect_error)?;
+                stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+                (Box::new(stream.try_clone()?), Box::new(stream))
+            };
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer: BufWriter::new(writer),
+            next_id: 0,
+        })
+    }
+
+    /// Calls an API function (e.g. `nvim_command`) and waits for its result. Notifications from
+    /// Neovim received meanwhile are ignored.
+    fn call(&mut self, method: &str, params: Vec<Value>) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        debug!("nvim request {id}: {method} {params:?}");
+        let request = Value::Array(vec![
+            REQUEST.into(),
+            id.into(),
+            method.into(),
+            Value::Array(params),
+        ]);
+        rmpv::encode::write_value(&mut self.writer, &request)?;
+        self.writer.flush()?;
+
+        loop {
+            let message = rmpv::decode::read_value(&mut self.reader)
+                .wrap_err_with(|| format!("No response from Neovim to {method}"))?;
+            let Value::Array(fields) = message else {
+                return Err(eyre!("Invalid message from Neovim: {message}"));
+            };
+            match fields.as_slice() {
+                [kind, response_id, error, result]
+                    if kind.as_u64() == Some(RESPONSE) && response_id.as_u64() == Some(id) =>
+                {
+                    if !error.is_nil() {
+                        return Err(eyre!("Neovim {method} failed: {}", error_message(error)));
+                    }
+                    return Ok(result.clone());
+                }
+                _ => debug!("Ignoring message from Neovim: {fields:?}"),
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Result<()> {
+        self.call("nvim_command", vec![command.into()])?;
+        Ok(())
+    }
+
+    fn call_function(&mut self, function: &str, args: Vec<Value>) -> Result<Value> {
+        self.call(
+            "nvim_call_function",
+            vec![function.into(), Value::Array(args)],
+        )
+    }
+}
+
+/// Errors are sent as `[type, message]`
+fn error_message(error: &Value) -> String {
+    match error.as_array().and_then(|error| error.get(1)) {
+        Some(Value::String(message)) => message.as_str().unwrap_or_default().to_owned(),
+        _ => error.to_string(),
+    }
+}
+
+/// Where `builtin:nvim` opens files
+#[derive(Debug, Default, PartialEq)]
+enum Placement {
+    #[default]
+    Current,
+    Split,
+    Vsplit,
+    Tab,
+    /// Add to the buffer list without showing them
+    Background,
+}
+
+impl Placement {
+    fn edit_command(&self) -> &'static str {
+        match self {
+            Self::Current => "edit",
+            Self::Split => "split",
+            Self::Vsplit => "vsplit",
+            Self::Tab => "tabedit",
+            Self::Background => "badd",
+        }
+    }
+}
+
+/// Options for `builtin:nvim`
+#[derive(Debug, Default, PartialEq)]
+struct NvimArgs {
+    server: Option<String>,
+    /// Open in the previous window (`wincmd p`)
+    previous: bool,
+    placement: Placement,
+    /// Close the window that was current (e.g. the terminal voxide was run from) afterwards
+    quit: bool,
+    quickfix: Option<PathBuf>,
+    /// Files, each with the line from a following `+<line>`
+    files: Vec<(PathBuf, Option<usize>)>,
+}
+
+impl NvimArgs {
+    /// Parses the options and files. `--` ends the options, so files after it can start with `-`
+    /// (a `+<line>` after a file still gives its line).
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut options_ended = false;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                line if line.starts_with('+') => {
+                    let line = line[1..]
+                        .parse()
+                        .wrap_err_with(|| format!("Invalid line number {line}"))?;
+                    let (_, file_line) = parsed
+                        .files
+                        .last_mut()
+                        .ok_or_else(|| eyre!("+{line} must follow a file"))?;
+                    *file_line = Some(line);
+                }
+                file if options_ended => parsed.files.push((PathBuf::from(file), None)),
+                "--" => options_ended = true,
+                "--server" => {
+                    let server = args
+                        .next()
+                        .ok_or_else(|| eyre!("--server requires an address"))?;
+                    parsed.server = Some(server.clone());
+                }
+                "--previous" => parsed.previous = true,
+                "--split" => parsed.placement = Placement::Split,
+                "--vsplit" => parsed.placement = Placement::Vsplit,
+                "--tab" => parsed.placement = Placement::Tab,
+                "--background" => parsed.placement = Placement::Background,
+                "--quit" => parsed.quit = true,
+                "-q" => {
+                    let file = args
+                        .next()
+                        .ok_or_else(|| eyre!("-q requires a quickfix file"))?;
+                    parsed.quickfix = Some(PathBuf::from(file));
+                }
+                flag if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(eyre!(
+                        "Unknown option {flag} for builtin:nvim (expected --server, --previous, --split, --vsplit, --tab, --background, --quit, -q, or -- before files)"
+                    ))
+                }
+                file => parsed.files.push((PathBuf::from(file), None)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// A `setqflist()` item for a quickfix line (e.g. `src/main.rs:12:3:text`) naming an existing
+/// file, made absolute since Neovim would resolve it against its own working directory
+fn quickfix_item(line: &str, cwd: &Path) -> Option<Value> {
+    let entry = QuickfixEntry::parse(line)?.absolute(cwd);
+    if !entry.path.exists() {
+        return None;
+    }
+    let mut item = vec![
+        (
+            "filename".into(),
+            entry.path.to_string_lossy().into_owned().into(),
+        ),
+        ("lnum".into(), entry.line.into()),
+    ];
+    if let Some(column) = entry.column {
+        item.push(("col".into(), column.into()));
+    }
+    item.push(("text".into(), entry.text.into()));
+    Some(Value::Map(item))
+}
+
+/// The `what` arguments of the `setqflist()` calls that build the list from a quickfix file, in
+/// order: runs of lines that `quickfix_item` makes items of are passed as `items`, and runs of
+/// other lines as `lines`, so Neovim still parses them with 'errorformat'
+fn quickfix_lists(contents: &str, cwd: &Path) -> Vec<Value> {
+    let mut lists: Vec<(bool, Vec<Value>)> = vec![];
+    for line in contents.lines() {
+        let (is_item, value) = match quickfix_item(line, cwd) {
+            Some(item) => (true, item),
+            None => (false, line.into()),
+        };
+        match lists.last_mut() {
+            Some((last_is_item, values)) if *last_is_item == is_item => values.push(value),
+            _ => lists.push((is_item, vec![value])),
+        }
+    }
+    if lists.is_empty() {
+        lists.push((true, vec![]));
+    }
+    lists
+        .into_iter()
+        .map(|(is_item, values)| {
+            let key = if is_item { "items" } else { "lines" };
+            Value::Map(vec![
+                (key.into(), Value::Array(values)),
+                ("title".into(), "voxide".into()),
+            ])
+        })
+        .collect()
+}
+
+fn path_value(path: &Path) -> Result<Value> {
+    path.to_str()
+        .map(Value::from)
+        .ok_or_else(|| eyre!("Invalid unicode in path {}", path.display()))
+}
+
+/// Opens the files (or quickfix list) in the Neovim connected to `client`. Relative paths are
+/// relative to `cwd`.
+fn open(client: &mut NvimClient, args: &NvimArgs, cwd: &Path) -> Result<()> {
+    let start_window = if args.quit {
+        Some(client.call("nvim_get_current_win", vec![])?)
+    } else {
+        None
+    };
+    if args.previous {
+        client.command("wincmd p")?;
+    }
+
+    if let Some(quickfix) = &args.quickfix {
+        let contents = fs::read_to_string(quickfix)
+            .wrap_err_with(|| format!("Failed to read quickfix file {}", quickfix.display()))?;
+        for (index, what) in quickfix_lists(&contents, cwd).into_iter().enumerate() {
+            // The first call starts a new list, and the rest add to it
+            let action = if index == 0 { " " } else { "a" };
+            client.call_function("setqflist", vec![Value::Array(vec![]), action.into(), what])?;
+        }
+        // Jumping to (or opening) a list without entries to go to fails with E42
+        let valid = client.call(
+            "nvim_eval",
+            vec!["len(filter(getqflist(), 'v:val.valid'))".into()],
+        )?;
+        if valid.as_u64().unwrap_or(0) == 0 {
+            debug!("No quickfix entries in {}", quickfix.display());
+        } else {
+            client.command(if args.placement == Placement::Background {
+                "copen"
+            } else {
+                "cfirst"
+            })?;
+        }
+    }
+
+    for (file, line) in &args.files {
+        let escaped = client.call_function("fnameescape", vec![path_value(&cwd.join(file))?])?;
+        let escaped = escaped
+            .as_str()
+            .ok_or_else(|| eyre!("Invalid fnameescape result {escaped}"))?;
+        let edit = args.placement.edit_command();
+        let background = args.placement == Placement::Background;
+        match line {
+            Some(line) if background => client.command(&format!("{edit} +{line} {escaped}"))?,
+            _ => client.command(&format!("{edit} {escaped}"))?,
+        }
+        if let (false, Some(line)) = (background, line) {
+            client.command(&line.to_string())?;
+        }
+    }
+
+    if let Some(start_window) = start_window {
+        // Don't close the window the files were just opened in
+        if client.call("nvim_get_current_win", vec![])? != start_window {
+            client.call("nvim_win_close", vec![start_window, true.into()])?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs a new `nvim` with the files, when there is no server to connect to (as nvr does)
+fn run_new_nvim(args: &NvimArgs) -> Result<()> {
+    let mut nvim = CommandWrapper::new_from_script_path("nvim")?;
+    if let Some(quickfix) = &args.quickfix {
+        nvim.arg("-q").arg(quickfix);
+    }
+    for (file, line) in &args.files {
+        // After `--`, nvim would take the `+<line>`s for files too
+        if file.as_os_str().as_encoded_bytes().starts_with(b"-") {
+            nvim.arg(Path::new(".").join(file));
+        } else {
+            nvim.arg(file);
+        }
+        if let Some(line) = line {
+            nvim.arg(format!("+{line}"));
+        }
+    }
+    nvim.run()
+}
+
+/// `builtin:nvim [--server <address>] [--previous] [--split|--vsplit|--tab|--background] [--quit]
+/// [-q <quickfix file>] [--] [<file> [+<line>]...]`: opens files (or a quickfix list) in a
+/// running Neovim, by default the one in `$NVIM` (set in Neovim's terminals). Without a server,
+/// runs a new `nvim`.
+pub fn nvim_source(args: &[String]) -> Result<()> {
+    let args = NvimArgs::parse(args)?;
+    let server = args.server.clone().or_else(|| {
+        ["NVIM", "NVIM_LISTEN_ADDRESS"].into_iter().find_map(|var| {
+            std::env::var(var)
+                .ok()
+                .filter(|address| !address.is_empty())
+        })
+    });
+    let Some(server) = server else {
+        debug!("No Neovim server found, running nvim");
+        return run_new_nvim(&args);
+    };
+    let mut client = NvimClient::connect(&server)?;
+    open(&mut client, &args, &std::env::current_dir()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    /// Stands in for Neovim: answers requests on a socket (with one window switched by `wincmd p`,
+    /// and a quickfix list of the `items` given to `setqflist()`) and returns them once the client
+    /// disconnects
+    fn stub_server(socket: &Path) -> Result<thread::JoinHandle<Vec<(String, Value)>>> {
+        let listener = UnixListener::bind(socket)?;
+        Ok(thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut requests = vec![];
+            let mut window = 1;
+            let mut quickfix_items = 0;
+            while let Ok(Value::Array(request)) = rmpv::decode::read_value(&mut reader) {
+                let (id, method, params) = (&request[1], request[2].as_str().unwrap(), &request[3]);
+                let (error, result) = match (method, &params[0]) {
+                    ("nvim_command", command) if command.as_str() == Some("wincmd p") => {
+                        window = 2;
+                        (Value::Nil, Value::Nil)
+                    }
+                    ("nvim_command", command) if command.as_str() == Some("bad") => (
+                        Value::Array(vec![0.into(), "Vim:E492: Not an editor command".into()]),
+                        Value::Nil,
+                    ),
+                    ("nvim_get_current_win", _) => (Value::Nil, window.into()),
+                    ("nvim_call_function", function) if function.as_str() == Some("setqflist") => {
+                        let what = params[1][2].as_map().unwrap();
+                        for (key, value) in what {
+                            if key.as_str() == Some("items") {
+                                quickfix_items += value.as_array().unwrap().len();
+                            }
+                        }
+                        (Value::Nil, 0.into())
+                    }
+                    ("nvim_eval", _) => (Value::Nil, quickfix_items.into()),
+                    ("nvim_call_function", _) => (Value::Nil, params[1][0].clone()),
+                    _ => (Value::Nil, Value::Nil),
+                };
+                // A notification, which the client should skip
+                let notification =
+                    Value::Array(vec![2.into(), "redraw".into(), Value::Array(vec![])]);
+                rmpv::encode::write_value(&mut writer, &notification).unwrap();
+                let response = Value::Array(vec![RESPONSE.into(), id.clone(), error, result]);
+                rmpv::encode::write_value(&mut writer, &response).unwrap();
+                requests.push((method.to_owned(), params.clone()));
+            }
+            requests
+        }))
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() -> Result<()> {
+        let args = NvimArgs::parse(&strings(&["--vsplit", "a.rs", "+12", "b.rs"]))?;
+        assert_eq!(args.placement, Placement::Vsplit);
+        assert_eq!(
+            args.files,
+            vec![
+                (PathBuf::from("a.rs"), Some(12)),
+                (PathBuf::from("b.rs"), None)
+            ]
+        );
+        assert!(NvimArgs::parse(&strings(&["+12"])).is_err());
+        assert!(NvimArgs::parse(&strings(&["--nope"])).is_err());
+
+        let args = NvimArgs::parse(&strings(&["--tab", "--", "-a.rs", "+3", "--tab"]))?;
+        assert_eq!(args.placement, Placement::Tab);
+        assert_eq!(
+            args.files,
+            vec![
+                (PathBuf::from("-a.rs"), Some(3)),
+                (PathBuf::from("--tab"), None)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_in_previous_window_and_quit() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("nvim.sock");
+        let server = stub_server(&socket)?;
+
+        let mut client = NvimClient::connect(socket.to_str().unwrap())?;
+        let args = NvimArgs::parse(&strings(&["--previous", "--quit", "a.rs", "+12"]))?;
+        open(&mut client, &args, dir.path())?;
+        let error = client.command("bad").unwrap_err();
+        assert!(error.to_string().contains("E492"), "{error}");
+        drop(client);
+
+        let a_rs = dir.path().join("a.rs").display().to_string();
+        let commands = server
+            .join()
+            .unwrap()
+            .into_iter()
+            .map(|(method, params)| match method.as_str() {
+                "nvim_command" => params[0].as_str().unwrap().to_owned(),
+                _ => format!("{method} {params}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            commands,
+            vec![
+                "nvim_get_current_win []".to_owned(),
+                "wincmd p".to_owned(),
+                format!("nvim_call_function [\"fnameescape\", [\"{a_rs}\"]]"),
+                format!("edit {a_rs}"),
+                "12".to_owned(),
+                "nvim_get_current_win []".to_owned(),
+                "nvim_win_close [1, true]".to_owned(),
+                "bad".to_owned(),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_quickfix_only_jumps_to_entries() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.rs"), "")?;
+        let quickfix = dir.path().join("quickfix");
+        let commands = |contents: &str| -> Result<Vec<String>> {
+            fs::write(&quickfix, contents)?;
+            let socket = dir.path().join("nvim.sock");
+            let _ = fs::remove_file(&socket);
+            let server = stub_server(&socket)?;
+            let mut client = NvimClient::connect(socket.to_str().unwrap())?;
+            let args = NvimArgs::parse(&strings(&["-q", quickfix.to_str().unwrap()]))?;
+            open(&mut client, &args, dir.path())?;
+            drop(client);
+            Ok(server
+                .join()
+                .unwrap()
+                .into_iter()
+                .filter(|(method, _)| method == "nvim_command")
+                .map(|(_, params)| params[0].as_str().unwrap().to_owned())
+                .collect())
+        };
+        assert_eq!(commands("a.rs:3:fn main\n")?, vec!["cfirst"]);
+        // cfirst would fail with E42
+        assert!(commands("")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_quickfix_lists() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cwd = dir.path();
+        fs::write(cwd.join("a.rs"), "")?;
+        let a_rs = cwd.join("a.rs").display().to_string();
+        let contents = "a.rs:3:1:fn main\na.rs:4:oops\na.rs:12\nlog:123:not a file\na.rs:5:x\n";
+        let lists = quickfix_lists(contents, cwd)
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lists,
+            vec![
+                format!(
+                    r#"{{"items": [{{"filename": "{a_rs}", "lnum": 3, "col": 1, "text": "fn main"}}, {{"filename": "{a_rs}", "lnum": 4, "text": "oops"}}], "title": "voxide"}}"#
+                ),
+                r#"{"lines": ["a.rs:12", "log:123:not a file"], "title": "voxide"}"#.to_owned(),
+                format!(
+                    r#"{{"items": [{{"filename": "{a_rs}", "lnum": 5, "text": "x"}}], "title": "voxide"}}"#
+                ),
+            ]
+        );
+        assert_eq!(
+            quickfix_lists("", cwd)[0].to_string(),
+            r#"{"items": [], "title": "voxide"}"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use crate::path_util;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use directories::BaseDirs;
+use regex::Regex;
+use rmpv::Value;
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// ShaDa entry types which hold marks (see `:help shada-format`)
+const GLOBAL_MARK: u64 = 7;
+const LOCAL_MARK: u64 = 10;
+/// The `"` mark: the last cursor position when the buffer was left
+const LAST_POSITION_MARK: u64 = b'"' as u64;
+/// Files which are never useful to reopen
+const IGNORED_FILES: [&str; 2] = ["COMMIT_EDITMSG", "git-rebase-todo"];
+
+/// A file mark from the ShaDa file
+#[derive(Debug, PartialEq)]
+struct FileMark {
+    path: PathBuf,
+    line: u64,
+    /// The mark's name, e.g. `"` or `A`
+    name: u64,
+    timestamp: u64,
+}
+
+/// $XDG_STATE_HOME/nvim/shada/main.shada (usually ~/.local/state/nvim/shada/main.shada)
+fn default_shada_path() -> Result<PathBuf> {
+    let state_dir = match std::env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => BaseDirs::new()
+            .ok_or_else(|| eyre!("Could not find home directory"))?
+            .home_dir()
+            .join(".local/state"),
+    };
+    Ok(state_dir.join("nvim/shada/main.shada"))
+}
+
+fn map_get<'a>(map: &'a [(Value, Value)], key: &str) -> Option<&'a Value> {
+    map.iter()
+        .find(|(k, _)| k.as_str() == Some(key))
+        .map(|(_, v)| v)
+}
+
+/// Parses a mark entry's data: a map with the file (`f`), line (`l`, default 1) and mark name
+/// (`n`, default `"`)
+fn parse_mark(data: &Value, timestamp: u64) -> Option<FileMark> {
+    let map = data.as_map()?;
+    let path = match map_get(map, "f")? {
+        Value::String(path) => PathBuf::from(path.as_str()?),
+        Value::Binary(path) => PathBuf::from(String::from_utf8(path.clone()).ok()?),
+        _ => return None,
+    };
+    Some(FileMark {
+        path,
+        line: map_get(map, "l").and_then(Value::as_u64).unwrap_or(1),
+        name: map_get(map, "n")
+            .and_then(Value::as_u64)
+            .unwrap_or(LAST_POSITION_MARK),
+        timestamp,
+    })
+}
+
+/// Reads the file marks from ShaDa data: a sequence of entries, each a type, timestamp and length
+/// followed by `length` bytes of msgpack data. Entries of other types are skipped.
+fn read_marks(shada: &[u8]) -> Result<Vec<FileMark>> {
+    let mut cursor = Cursor::new(shada);
+    let mut marks = vec![];
+    let read_uint = |cursor: &mut Cursor<&[u8]>| -> Result<u64> {
+        rmpv::decode::read_value(cursor)?
+            .as_u64()
+            .ok_or_else(|| eyre!("Invalid ShaDa entry header"))
+    };
+
+    while (cursor.position() as usize) < shada.len() {
+        let entry_type = read_uint(&mut cursor)?;
+        let timestamp = read_uint(&mut cursor)?;
+        let length = read_uint(&mut cursor)? as usize;
+        let start = cursor.position() as usize;
+        let end = start
+            .checked_add(length)
+            .filter(|end| *end <= shada.len())
+            .ok_or_else(|| eyre!("Truncated ShaDa entry at byte {start}"))?;
+        cursor.set_position(end as u64);
+
+        if entry_type == GLOBAL_MARK || entry_type == LOCAL_MARK {
+            let data = rmpv::decode::read_value(&mut &shada[start..end])?;
+            marks.extend(parse_mark(&data, timestamp));
+        }
+    }
+    Ok(marks)
+}
+
+/// The files with marks, most recently used first, each with the line of its last cursor
+/// position (or its most recent mark)
+fn oldfiles(mut marks: Vec<FileMark>) -> Vec<(PathBuf, u64)> {
+    // Most recent first, with the last-position mark first among marks of the same age
+    marks.sort_by_key(|mark| {
+        (
+            std::cmp::Reverse(mark.timestamp),
+            mark.name != LAST_POSITION_MARK,
+        )
+    });
+    let mut seen = HashSet::new();
+    marks
+        .into_iter()
+        .filter(|mark| seen.insert(mark.path.clone()))
+        .map(|mark| (mark.path, mark.line))
+        .collect()
+}
+
+/// Options for `builtin:nvim-oldfiles`
+#[derive(Debug, Default)]
+struct OldfilesArgs {
+    /// Include files outside the current repository (or directory, if not in a repository)
+    all: bool,
+    /// Don't append `:<line>`
+    no_lines: bool,
+    file: Option<PathBuf>,
+    pattern: Option<Regex>,
+}
+
+impl OldfilesArgs {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--all" => parsed.all = true,
+                "--no-lines" => parsed.no_lines = true,
+                "--file" => {
+                    let file = args
+                        .next()
+                        .ok_or_else(|| eyre!("--file requires a ShaDa file path"))?;
+                    parsed.file = Some(PathBuf::from(file));
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(eyre!(
+                        "Unknown option {flag} for builtin:nvim-oldfiles (expected --all, --no-lines, --file)"
+                    ))
+                }
+                pattern if parsed.pattern.is_none() => {
+                    parsed.pattern = Some(
+                        Regex::new(pattern)
+                            .wrap_err_with(|| format!("Invalid pattern {pattern}"))?,
+                    )
+                }
+                _ => return Err(eyre!("builtin:nvim-oldfiles takes at most one pattern")),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// The lines to print for `builtin:nvim-oldfiles`. Paths under `cwd` are made relative to it.
+fn oldfiles_lines(files: Vec<(PathBuf, u64)>, args: &OldfilesArgs, cwd: &Path) -> Vec<String> {
+    let scope = path_util::scope_dir(cwd);
+    files
+        .into_iter()
+        .filter(|(path, _)| args.all || path.starts_with(scope))
+        .filter(|(path, _)| {
+            !path
+                .file_name()
+                .is_some_and(|name| IGNORED_FILES.iter().any(|ignored| name == *ignored))
+        })
+        .filter(|(path, _)| path.is_file())
+        .filter_map(|(path, line)| {
+            let path = path.strip_prefix(cwd).unwrap_or(&path).to_str()?.to_owned();
+            if args.pattern.as_ref().is_some_and(|re| !re.is_match(&path)) {
+                return None;
+            }
+            Some(if args.no_lines {
+                path
+            } else {
+                format!("{path}:{line}")
+            })
+        })
+        .collect()
+}
+
+/// `builtin:nvim-oldfiles [--all] [--no-lines] [--file <shada>] [<regex>]`: prints the files in
+/// Neovim's `:oldfiles` (read from its ShaDa file) in the current repository (or under the current
+/// directory), most recent first, as `<path>:<line of last cursor position>`
+pub fn oldfiles_source(args: &[String]) -> Result<()> {
+    let args = OldfilesArgs::parse(args)?;
+    let path = match &args.file {
+        Some(file) => file.clone(),
+        None => default_shada_path()?,
+    };
+    let shada = std::fs::read(&path)
+        .wrap_err_with(|| format!("Failed to read ShaDa file {}", path.display()))?;
+    let marks = read_marks(&shada)
+        .wrap_err_with(|| format!("Failed to parse ShaDa file {}", path.display()))?;
+    let cwd = std::env::current_dir()?.canonicalize()?;
+    for line in oldfiles_lines(oldfiles(marks), &args, &cwd) {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Encodes a ShaDa entry
+    fn entry(entry_type: u64, timestamp: u64, data: Value) -> Vec<u8> {
+        let mut data_bytes = vec![];
+        rmpv::encode::write_value(&mut data_bytes, &data).unwrap();
+        let mut bytes = vec![];
+        for header in [entry_type, timestamp, data_bytes.len() as u64] {
+            rmpv::encode::write_value(&mut bytes, &Value::from(header)).unwrap();
+        }
+        bytes.extend(data_bytes);
+        bytes
+    }
+
+    fn mark(file: &str, line: u64, name: Option<char>) -> Value {
+        let mut map = vec![
+            (Value::from("f"), Value::from(file)),
+            (Value::from("l"), Value::from(line)),
+        ];
+        if let Some(name) = name {
+            map.push((Value::from("n"), Value::from(name as u64)));
+        }
+        Value::Map(map)
+    }
+
+    #[test]
+    fn test_read_oldfiles() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path().canonicalize()?;
+        let repo = dir.join("repo");
+        fs::create_dir_all(repo.join(".git"))?;
+        for file in ["a.rs", "b.rs", "COMMIT_EDITMSG"] {
+            fs::write(repo.join(file), "")?;
+        }
+        fs::write(dir.join("other.rs"), "")?;
+        let path = |file: &str| dir.join(file).display().to_string();
+
+        let shada = [
+            // Header, with data that isn't a mark
+            entry(1, 100, Value::Map(vec![])),
+            entry(LOCAL_MARK, 200, mark(&path("repo/a.rs"), 12, None)),
+            entry(LOCAL_MARK, 200, mark(&path("repo/a.rs"), 3, Some('a'))),
+            entry(GLOBAL_MARK, 300, mark(&path("repo/b.rs"), 40, Some('B'))),
+            entry(LOCAL_MARK, 250, mark(&path("repo/COMMIT_EDITMSG"), 1, None)),
+            entry(LOCAL_MARK, 150, mark(&path("other.rs"), 5, None)),
+            entry(LOCAL_MARK, 50, mark(&path("repo/gone.rs"), 5, None)),
+        ]
+        .concat();
+
+        let files = oldfiles(read_marks(&shada)?);
+        assert_eq!(files[0], (repo.join("b.rs"), 40));
+        assert_eq!(files[2], (repo.join("a.rs"), 12));
+
+        let args = OldfilesArgs::default();
+        assert_eq!(
+            oldfiles_lines(oldfiles(read_marks(&shada)?), &args, &repo),
+            vec!["b.rs:40", "a.rs:12"]
+        );
+        let args = OldfilesArgs::parse(&["--all".to_owned(), "--no-lines".to_owned()])?;
+        assert_eq!(
+            oldfiles_lines(files, &args, &repo),
+            vec!["b.rs", "a.rs", &path("other.rs")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_shada() {
+        let mut shada = entry(LOCAL_MARK, 1, mark("/a", 1, None));
+        shada.truncate(shada.len() - 2);
+        assert!(read_marks(&shada).is_err());
+    }
+}
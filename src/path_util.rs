@@ -1,10 +1,18 @@
 use color_eyre::eyre::{eyre, Result};
 use directories::UserDirs;
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-const CONFIG_DIR: &str = ".config/voxide";
-const CONFIG_SCRIPTS_DIR: &str = ".config/voxide/scripts";
-const SHARE_SCRIPTS_DIR: &str = ".local/share/voxide/scripts";
+const SCRIPTS_DIR: &str = "scripts";
+/// Colon-separated list of config directories (or files), lowest precedence first
+const CONFIG_DIR_ENV_VAR: &str = "VOXIDE_CONFIG_DIR";
+const DEFAULT_XDG_CONFIG_DIRS: &str = "/etc/xdg";
+/// Presence of any of these marks a directory as the root of a repository
+const VCS_DIRS: [&str; 4] = [".git", ".hg", ".jj", ".svn"];
+
+/// Config paths given with --config, which take the place of VOXIDE_CONFIG_DIR/the default
+static CONFIG_PATHS_OVERRIDE: OnceLock<Vec<PathBuf>> = OnceLock::new();
 
 pub fn home_dir() -> Result<PathBuf> {
     Ok(UserDirs::new()
@@ -13,27 +21,121 @@ pub fn home_dir() -> Result<PathBuf> {
         .to_path_buf())
 }
 
+/// The root of the repository (git, hg, jj or svn) containing `dir`, if any
+pub fn vcs_root(dir: &Path) -> Option<&Path> {
+    dir.ancestors()
+        .find(|dir| VCS_DIRS.iter().any(|vcs| dir.join(vcs).exists()))
+}
+
+/// The directory file sources list files under by default: the repository containing `cwd`, or
+/// `cwd` itself outside of a repository
+pub fn scope_dir(cwd: &Path) -> &Path {
+    vcs_root(cwd).unwrap_or(cwd)
+}
+
+/// Use these config paths (from --config) instead of VOXIDE_CONFIG_DIR or the default
+pub fn set_config_paths(paths: Vec<PathBuf>) {
+    let _ = CONFIG_PATHS_OVERRIDE.set(paths);
+}
+
+/// $XDG_CONFIG_DIRS (default /etc/xdg), most important first
+pub fn xdg_config_dirs() -> Vec<PathBuf> {
+    let dirs = std::env::var_os("XDG_CONFIG_DIRS")
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| DEFAULT_XDG_CONFIG_DIRS.into());
+    std::env::split_paths(&dirs)
+        .filter(|dir| dir.is_absolute())
+        .collect()
+}
+
+/// An XDG base directory from the value of its variable, or `default` (relative to the home dir)
+/// if unset or relative. These are used on every platform (e.g. ~/.config rather than
+/// ~/Library/Application Support on macOS), so configs are where people expect them.
+fn xdg_dir(value: Option<OsString>, default: &str) -> Result<PathBuf> {
+    match value.map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => Ok(dir),
+        _ => Ok(home_dir()?.join(default)),
+    }
+}
+
+/// $XDG_CONFIG_HOME (usually ~/.config)
+pub fn xdg_config_home() -> Result<PathBuf> {
+    xdg_dir(std::env::var_os("XDG_CONFIG_HOME"), ".config")
+}
+
+/// $XDG_DATA_HOME (usually ~/.local/share)
+pub fn xdg_data_home() -> Result<PathBuf> {
+    xdg_dir(std::env::var_os("XDG_DATA_HOME"), ".local/share")
+}
+
+/// System-wide config directories: voxide/ in each of $XDG_CONFIG_DIRS, lowest precedence first
+fn system_config_dirs() -> Vec<PathBuf> {
+    let mut dirs = xdg_config_dirs()
+        .into_iter()
+        .map(|dir| dir.join("voxide"))
+        .collect::<Vec<_>>();
+    dirs.reverse();
+    dirs
+}
+
+/// Config directories (or individual config files), lowest precedence first: the paths given with
+/// --config, or else those in VOXIDE_CONFIG_DIR, or else the system config dirs followed by
+/// $XDG_CONFIG_HOME/voxide (usually ~/.config/voxide)
+pub fn config_paths() -> Result<Vec<PathBuf>> {
+    if let Some(paths) = CONFIG_PATHS_OVERRIDE.get() {
+        return Ok(paths.clone());
+    }
+    match std::env::var_os(CONFIG_DIR_ENV_VAR) {
+        Some(dirs) if !dirs.is_empty() => Ok(std::env::split_paths(&dirs).collect()),
+        _ => {
+            let mut paths = system_config_dirs();
+            paths.push(xdg_config_home()?.join("voxide"));
+            Ok(paths)
+        }
+    }
+}
+
+/// The config paths, joined like PATH (for scripts, in VOXIDE_CONFIG_PATHS)
+pub fn config_paths_env_value() -> Result<String> {
+    Ok(std::env::join_paths(config_paths()?)?
+        .to_string_lossy()
+        .into_owned())
+}
+
+/// `--config` arguments for the paths given with --config, for builtins (which are part of this
+/// run, unlike voxides that scripts or the editor run)
+pub fn config_override_args() -> Vec<OsString> {
+    CONFIG_PATHS_OVERRIDE
+        .get()
+        .into_iter()
+        .flatten()
+        .flat_map(|path| [OsString::from("--config"), path.into()])
+        .collect()
+}
+
+/// The personal config directory: the last (highest precedence) config path, which is where
+/// --init installs the example config
 pub fn config_dir() -> Result<PathBuf> {
-    home_dir().map(|path| path.join(CONFIG_DIR))
+    config_paths()?
+        .pop()
+        .ok_or_else(|| eyre!("No config directories given"))
 }
 
-pub fn share_scripts_dir() -> Result<PathBuf> {
-    home_dir().map(|path| path.join(SHARE_SCRIPTS_DIR))
+/// $XDG_DATA_HOME/voxide (usually ~/.local/share/voxide)
+pub fn data_dir() -> Result<PathBuf> {
+    Ok(xdg_data_home()?.join("voxide"))
 }
 
-pub fn config_scripts_dir() -> Result<PathBuf> {
-    home_dir().map(|path| path.join(CONFIG_SCRIPTS_DIR))
+pub fn share_scripts_dir() -> Result<PathBuf> {
+    data_dir().map(|path| path.join(SCRIPTS_DIR))
 }
 
-fn path_from_home_if_exists(rel_path: &str) -> Option<String> {
-    home_dir()
-        .ok()
-        .map(|path| path.join(rel_path))
-        .filter(|p| p.exists())
-        .and_then(|p| p.to_str().map(str::to_owned))
+pub fn config_scripts_dir() -> Result<PathBuf> {
+    config_dir().map(|path| path.join(SCRIPTS_DIR))
 }
 
-/// Gets PATH but prepends ~/.config/voxide/scripts and ~/.local/share/voxide/scripts
+/// Gets PATH but prepends the scripts directories in the config paths (highest precedence first)
+/// and in the data dir (~/.config/voxide/scripts and ~/.local/share/voxide/scripts by default)
 pub fn path_with_prepended_script_paths() -> String {
     let path = std::env::var("PATH").ok();
     let path = match path.as_deref() {
@@ -44,17 +146,46 @@ pub fn path_with_prepended_script_paths() -> String {
         eprintln!("Warning: PATH not set or invalid");
     }
 
-    let conf_scripts = path_from_home_if_exists(CONFIG_SCRIPTS_DIR);
-    let share_scripts = path_from_home_if_exists(SHARE_SCRIPTS_DIR);
+    let mut script_dirs = config_paths().unwrap_or_default();
+    script_dirs.reverse();
+    let script_dirs = script_dirs
+        .into_iter()
+        .map(|dir| dir.join(SCRIPTS_DIR))
+        .chain(share_scripts_dir().ok())
+        .filter(|p| p.is_dir())
+        .filter_map(|p| p.to_str().map(str::to_owned))
+        .collect::<Vec<_>>();
 
-    if conf_scripts.is_none() && share_scripts.is_none() {
+    if script_dirs.is_empty() {
         eprintln!("Warning: No scripts directory found in config or share directories. To install default scripts/config run with --init; run with --help for more info.");
     }
 
-    let res = [conf_scripts, share_scripts, path]
+    let res = script_dirs
         .iter()
-        .filter_map(|x| x.as_deref())
+        .map(String::as_str)
+        .chain(path.as_deref())
         .collect::<Vec<&str>>()
         .join(":");
     res
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xdg_dir() -> Result<()> {
+        let home = home_dir()?;
+        assert_eq!(
+            xdg_dir(Some("/xdg/config".into()), ".config")?,
+            PathBuf::from("/xdg/config")
+        );
+        assert_eq!(xdg_dir(None, ".config")?, home.join(".config"));
+        // Relative values are ignored, as the spec says
+        assert_eq!(
+            xdg_dir(Some("relative".into()), ".local/share")?,
+            home.join(".local/share")
+        );
+        Ok(())
+    }
+}
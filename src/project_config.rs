@@ -0,0 +1,376 @@
+use crate::config::{ConfigFile, CONFIG_EXTENSIONS};
+use crate::config_include::resolve_includes;
+use crate::path_util;
+use color_eyre::eyre::{Result, WrapErr};
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Project config files (without extension, see `CONFIG_EXTENSIONS`), checked in each directory
+/// from the cwd up to the VCS root
+const PROJECT_CONFIG_FILES: [&str; 2] = [".voxide", ".config/voxide"];
+/// File in the data dir listing trusted project configs, as lines of "<sha256>  <path>"
+const TRUSTED_PROJECTS_FILE: &str = "trusted-projects";
+/// The trusted project configs (and their includes), joined like PATH. Passed to scripts and
+/// filters so voxides they run (e.g. builtins, which read the config) use the same ones without
+/// asking again on the terminal, which the filter may be reading.
+pub const PROJECT_CONFIGS_ENV_VAR: &str = "VOXIDE_PROJECT_CONFIGS";
+
+/// The project configs passed on in `PROJECT_CONFIGS_ENV_VAR` by the voxide running this one
+static INHERITED_PROJECT_CONFIGS: OnceLock<Option<Vec<PathBuf>>> = OnceLock::new();
+static TRUSTED_PROJECT_CONFIGS: OnceLock<Vec<ConfigFile>> = OnceLock::new();
+
+/// Finds project config files from the cwd up to the VCS root, outermost first (so configs closer
+/// to the cwd take precedence). Outside of a repository, only the cwd is checked.
+pub fn find_project_configs(cwd: &Path) -> Vec<PathBuf> {
+    let dirs = match path_util::vcs_root(cwd) {
+        Some(root) => cwd
+            .ancestors()
+            .take_while(|dir| dir.starts_with(root))
+            .collect(),
+        None => vec![cwd],
+    };
+
+    let mut files = dirs
+        .iter()
+        .flat_map(|dir| {
+            PROJECT_CONFIG_FILES.iter().flat_map(move |file| {
+                CONFIG_EXTENSIONS
+                    .iter()
+                    .map(move |ext| dir.join(format!("{file}.{ext}")))
+            })
+        })
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    files.reverse();
+    files
+}
+
+/// A project config file and the hash of its contents, which is what is trusted (so a config is
+/// trusted again after it changes). The contents are kept so that what gets loaded is exactly what
+/// was hashed, even if the file changes in between.
+struct TrustEntry {
+    hash: String,
+    path: PathBuf,
+    contents: String,
+}
+
+impl TrustEntry {
+    fn new(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read project config {}", path.display()))?;
+        Ok(Self {
+            hash: format!("{:x}", Sha256::digest(&contents)),
+            path: path.canonicalize()?,
+            contents,
+        })
+    }
+
+    fn into_config_file(self) -> ConfigFile {
+        ConfigFile {
+            path: self.path,
+            contents: Some(self.contents),
+        }
+    }
+
+    fn line(&self) -> String {
+        format!("{}  {}", self.hash, self.path.display())
+    }
+}
+
+fn trusted_projects_path() -> Result<PathBuf> {
+    path_util::data_dir().map(|dir| dir.join(TRUSTED_PROJECTS_FILE))
+}
+
+/// Whether `entry` is in the list of trusted configs at `trusted_list`
+fn is_trusted(entry: &TrustEntry, trusted_list: &Path) -> Result<bool> {
+    if !trusted_list.exists() {
+        return Ok(false);
+    }
+    let line = entry.line();
+    for trusted in BufReader::new(fs::File::open(trusted_list)?).lines() {
+        if trusted? == line {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn trust(entry: &TrustEntry, trusted_list: &Path) -> Result<()> {
+    if let Some(dir) = trusted_list.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(trusted_list)?;
+    writeln!(file, "{}", entry.line())
+        .wrap_err_with(|| format!("Failed to write {}", trusted_list.display()))
+}
+
+/// Asks on the terminal whether to trust a project config. Reads from /dev/tty since stdin may be
+/// piped.
+fn prompt_trust(path: &Path) -> Result<bool> {
+    if !std::io::stderr().is_terminal() {
+        return Ok(false);
+    }
+    let Ok(tty) = fs::File::open("/dev/tty") else {
+        return Ok(false);
+    };
+    eprint!(
+        "Found project config {}, which can run arbitrary commands. Trust it? [y/N] ",
+        path.display()
+    );
+    let mut answer = String::new();
+    BufReader::new(tty).read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// The files in `configs` and those they `include` (included files first) which are trusted
+/// according to `trusted_list`, asking `prompt` about any that aren't (or that have changed since
+/// they were trusted). Included files are checked too, since they can change (or, for a glob, be
+/// added) without the project config changing. A file included from an untrusted config isn't
+/// asked about.
+fn trusted_files(
+    configs: &[PathBuf],
+    trusted_list: &Path,
+    prompt: impl Fn(&Path) -> Result<bool>,
+) -> Result<Vec<ConfigFile>> {
+    let check = |path: &Path| -> Result<Option<ConfigFile>> {
+        let entry = TrustEntry::new(path)?;
+        if is_trusted(&entry, trusted_list)? {
+            debug!("Using trusted project config {}", path.display());
+            Ok(Some(entry.into_config_file()))
+        } else if prompt(path)? {
+            trust(&entry, trusted_list)?;
+            Ok(Some(entry.into_config_file()))
+        } else {
+            eprintln!(
+                "Warning: skipping untrusted project config {} (run 'voxide --trust' to trust it)",
+                path.display()
+            );
+            Ok(None)
+        }
+    };
+    let mut checked = vec![];
+    let mut trusted = vec![];
+    for config in configs {
+        // Includes are resolved before the config itself
+        let files = resolve_includes(std::slice::from_ref(config))?;
+        let Some((config, included)) = files.split_last() else {
+            continue;
+        };
+        if checked.contains(config) {
+            continue;
+        }
+        checked.push(config.clone());
+        let Some(config) = check(config)? else {
+            continue;
+        };
+        for path in included {
+            if !checked.contains(path) {
+                checked.push(path.clone());
+                trusted.extend(check(path)?);
+            }
+        }
+        trusted.push(config);
+    }
+    Ok(trusted)
+}
+
+/// The files in `paths` (passed on by another voxide) which are still trusted according to
+/// `trusted_list`, in case any changed since they were checked
+fn still_trusted(paths: &[PathBuf], trusted_list: &Path) -> Result<Vec<ConfigFile>> {
+    let mut trusted = vec![];
+    for path in paths {
+        let entry = TrustEntry::new(path)?;
+        if is_trusted(&entry, trusted_list)? {
+            trusted.push(entry.into_config_file());
+        } else {
+            eprintln!(
+                "Warning: skipping project config {}, which is no longer trusted",
+                path.display()
+            );
+        }
+    }
+    Ok(trusted)
+}
+
+/// Reads `PROJECT_CONFIGS_ENV_VAR` from the voxide running this one (if any), removing it so the
+/// editor and the shells it starts (which may be in other projects) don't inherit it
+pub fn take_inherited_project_configs() {
+    let inherited = std::env::var_os(PROJECT_CONFIGS_ENV_VAR).map(|paths| {
+        std::env::split_paths(&paths)
+            .filter(|path| !path.as_os_str().is_empty())
+            .collect()
+    });
+    std::env::remove_var(PROJECT_CONFIGS_ENV_VAR);
+    let _ = INHERITED_PROJECT_CONFIGS.set(inherited);
+}
+
+/// Returns the project configs (see `find_project_configs`) and the files they include which are
+/// trusted, prompting for any that aren't (or that have changed since they were trusted) if on a
+/// terminal. When run by another voxide, uses the ones it found (see `PROJECT_CONFIGS_ENV_VAR`).
+/// Each comes with the contents that were checked, which are what should be loaded.
+pub fn trusted_project_configs() -> Result<Vec<ConfigFile>> {
+    if let Some(configs) = TRUSTED_PROJECT_CONFIGS.get() {
+        return Ok(configs.clone());
+    }
+    let trusted_list = trusted_projects_path()?;
+    let configs = match INHERITED_PROJECT_CONFIGS.get() {
+        Some(Some(paths)) => still_trusted(paths, &trusted_list)?,
+        _ => trusted_files(
+            &find_project_configs(&std::env::current_dir()?),
+            &trusted_list,
+            prompt_trust,
+        )?,
+    };
+    Ok(TRUSTED_PROJECT_CONFIGS.get_or_init(|| configs).clone())
+}
+
+/// The trusted project configs, joined like PATH (for passing on in `PROJECT_CONFIGS_ENV_VAR`)
+pub fn project_configs_env_value() -> Result<String> {
+    let paths = trusted_project_configs()?
+        .into_iter()
+        .map(|config| config.path);
+    Ok(std::env::join_paths(paths)?.to_string_lossy().into_owned())
+}
+
+/// Trusts all project configs found from the cwd, and the files they include (for --trust)
+pub fn trust_project_configs() -> Result<()> {
+    let paths = resolve_includes(&find_project_configs(&std::env::current_dir()?))?;
+    if paths.is_empty() {
+        eprintln!("No project config files found");
+    }
+    let trusted_list = trusted_projects_path()?;
+    for path in paths {
+        let entry = TrustEntry::new(&path)?;
+        if !is_trusted(&entry, &trusted_list)? {
+            trust(&entry, &trusted_list)?;
+        }
+        eprintln!("Trusted {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config_figment;
+
+    fn paths(files: Vec<ConfigFile>) -> Vec<PathBuf> {
+        files.into_iter().map(|file| file.path).collect()
+    }
+
+    #[test]
+    fn test_find_project_configs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().join("repo");
+        let sub = root.join("app/models");
+        fs::create_dir_all(&sub)?;
+        fs::create_dir_all(root.join(".git"))?;
+        fs::create_dir_all(root.join(".config"))?;
+        fs::write(dir.path().join(".voxide.toml"), "")?;
+        fs::write(root.join(".config/voxide.toml"), "")?;
+        fs::write(root.join("app/.voxide.toml"), "")?;
+
+        assert_eq!(
+            find_project_configs(&sub),
+            vec![
+                root.join(".config/voxide.toml"),
+                root.join("app/.voxide.toml")
+            ]
+        );
+        // Outside of a repository only the cwd is checked
+        assert_eq!(
+            find_project_configs(dir.path()),
+            vec![dir.path().join(".voxide.toml")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_includes_need_trust() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path().canonicalize()?;
+        let config = dir.join(".voxide.toml");
+        let extra = dir.join("extra/modes.toml");
+        fs::create_dir_all(extra.parent().unwrap())?;
+        fs::write(&config, "include = ['extra/*.toml']")?;
+        fs::write(&extra, "")?;
+        let trusted_list = dir.join("trusted-projects");
+        let trusted = || {
+            trusted_files(std::slice::from_ref(&config), &trusted_list, |_| Ok(false)).map(paths)
+        };
+
+        assert!(trusted()?.is_empty());
+        // Trusting the project config doesn't trust what it includes
+        trust(&TrustEntry::new(&config)?, &trusted_list)?;
+        assert_eq!(trusted()?, vec![config.clone()]);
+        trust(&TrustEntry::new(&extra)?, &trusted_list)?;
+        assert_eq!(trusted()?, vec![extra.clone(), config.clone()]);
+        // ...and a changed or newly matched include needs trusting again
+        fs::write(&extra, "[modes]")?;
+        fs::write(dir.join("extra/new.toml"), "")?;
+        assert_eq!(trusted()?, vec![config.clone()]);
+
+        // Answering yes to the prompt trusts it
+        let prompted = trusted_files(std::slice::from_ref(&config), &trusted_list, |_| Ok(true))?;
+        assert_eq!(
+            paths(prompted),
+            vec![
+                dir.join("extra/modes.toml"),
+                dir.join("extra/new.toml"),
+                config.clone()
+            ]
+        );
+        assert_eq!(trusted()?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_still_trusted() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path().canonicalize()?;
+        let configs = [dir.join("a.toml"), dir.join("b.toml")];
+        let trusted_list = dir.join("trusted-projects");
+        for config in &configs {
+            fs::write(config, "")?;
+            trust(&TrustEntry::new(config)?, &trusted_list)?;
+        }
+        assert_eq!(
+            paths(still_trusted(&configs, &trusted_list)?),
+            configs.to_vec()
+        );
+        fs::write(&configs[0], "[modes]")?;
+        assert_eq!(
+            paths(still_trusted(&configs, &trusted_list)?),
+            vec![configs[1].clone()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_loads_trusted_contents() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path().canonicalize()?;
+        let config = dir.join(".voxide.toml");
+        let trusted_list = dir.join("trusted-projects");
+        fs::write(&config, "stdin_mode = 'trusted'")?;
+        let trusted = trusted_files(std::slice::from_ref(&config), &trusted_list, |_| Ok(true))?;
+
+        // A change after the config was checked isn't loaded
+        fs::write(&config, "stdin_mode = 'changed'")?;
+        let figment = config_figment(&trusted);
+        assert_eq!(figment.extract_inner::<String>("stdin_mode")?, "trusted");
+        let source = figment
+            .find_metadata("stdin_mode")
+            .and_then(|metadata| metadata.source.as_ref())
+            .map(ToString::to_string);
+        assert_eq!(source, Some(config.display().to_string()));
+        Ok(())
+    }
+}
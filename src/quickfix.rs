@@ -0,0 +1,84 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// One location in a quickfix list, written as `path:line:column:text` (`%f:%l:%c:%m`, which
+/// Vim's default 'errorformat' reads). The column is in bytes, counting from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuickfixEntry {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: Option<usize>,
+    pub text: String,
+}
+
+impl QuickfixEntry {
+    /// Parses a `path:line:column:text` or `path:line:text` line (as output by e.g. `rg --vimgrep`
+    /// or `ag`)
+    pub fn parse(line: &str) -> Option<Self> {
+        let (path, rest) = line.split_once(':')?;
+        let (line, rest) = rest.split_once(':')?;
+        let line = line.parse().ok()?;
+        let (column, text) = match rest.split_once(':') {
+            Some((column, text)) if column.parse::<usize>().is_ok() => (column.parse().ok(), text),
+            _ => (None, rest),
+        };
+        (!path.is_empty()).then(|| Self {
+            path: PathBuf::from(path),
+            line,
+            column,
+            text: text.to_owned(),
+        })
+    }
+
+    /// The entry with a relative path resolved against `dir`
+    pub fn absolute(self, dir: &Path) -> Self {
+        Self {
+            path: dir.join(&self.path),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for QuickfixEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:", self.path.display(), self.line)?;
+        if let Some(column) = self.column {
+            write!(f, "{column}:")?;
+        }
+        write!(f, "{}", self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        let entry = QuickfixEntry::parse("src/main.rs:12:5:fn main() {").unwrap();
+        assert_eq!(
+            entry,
+            QuickfixEntry {
+                path: PathBuf::from("src/main.rs"),
+                line: 12,
+                column: Some(5),
+                text: "fn main() {".to_owned(),
+            }
+        );
+        assert_eq!(entry.to_string(), "src/main.rs:12:5:fn main() {");
+
+        let entry = QuickfixEntry::parse("a.rs:3:let x = a::b;").unwrap();
+        assert_eq!((entry.column, entry.text.as_str()), (None, "let x = a::b;"));
+        assert_eq!(entry.to_string(), "a.rs:3:let x = a::b;");
+
+        assert_eq!(QuickfixEntry::parse("a.rs"), None);
+        assert_eq!(QuickfixEntry::parse("a.rs:x:text"), None);
+        assert_eq!(
+            QuickfixEntry::parse("a.rs:1:2:x")
+                .unwrap()
+                .absolute(Path::new("/src"))
+                .path,
+            PathBuf::from("/src/a.rs")
+        );
+    }
+}
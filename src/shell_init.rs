@@ -0,0 +1,112 @@
+use crate::completions::completion_script;
+use color_eyre::eyre::{eyre, Result};
+
+/// Shells `--init-shell` supports
+pub const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+/// When set to a file path, voxide writes a directory result to that file instead of opening it,
+/// so the calling shell can cd there (used by the `vcd` function)
+pub const CD_FILE_ENV_VAR: &str = "VOXIDE_CD_FILE";
+
+/// Functions for bash: `__NAME__` runs voxide, `__NAME__cd` changes to a directory result, and
+/// Alt-v runs `__NAME__` with the current command line as its arguments
+const BASH: &str = r#"__NAME__() {
+    command voxide "$@"
+}
+
+__NAME__cd() {
+    local cd_file status dir
+    cd_file="$(mktemp -t voxide-cd.XXXXXX)" || return
+    VOXIDE_CD_FILE="$cd_file" command voxide "$@"
+    status=$?
+    dir="$(<"$cd_file")"
+    command rm -f -- "$cd_file"
+    if [[ -n $dir ]]; then
+        builtin cd -- "$dir" || return
+    fi
+    return $status
+}
+
+__voxide_widget() {
+    local line="$READLINE_LINE"
+    READLINE_LINE=""
+    READLINE_POINT=0
+    eval "__NAME__ $line" </dev/tty
+}
+[[ $- == *i* ]] && bind -x '"\ev": __voxide_widget'
+"#;
+
+const ZSH: &str = r#"__NAME__() {
+    command voxide "$@"
+}
+
+__NAME__cd() {
+    local cd_file exit_status dir
+    cd_file="$(mktemp -t voxide-cd.XXXXXX)" || return
+    VOXIDE_CD_FILE="$cd_file" command voxide "$@"
+    exit_status=$?
+    dir="$(<"$cd_file")"
+    command rm -f -- "$cd_file"
+    if [[ -n $dir ]]; then
+        builtin cd -- "$dir" || return
+    fi
+    return $exit_status
+}
+
+__voxide_widget() {
+    local line="$BUFFER"
+    BUFFER=""
+    zle -I
+    eval "__NAME__ $line" </dev/tty
+    zle reset-prompt
+}
+zle -N __voxide_widget
+bindkey '\ev' __voxide_widget
+"#;
+
+const FISH: &str = r#"function __NAME__ --wraps voxide
+    command voxide $argv
+end
+
+function __NAME__cd
+    set -l cd_file (mktemp -t voxide-cd.XXXXXX); or return
+    VOXIDE_CD_FILE=$cd_file command voxide $argv
+    set -l exit_status $status
+    set -l dir (cat -- $cd_file)
+    command rm -f -- $cd_file
+    if test -n "$dir"
+        builtin cd -- $dir; or return
+    end
+    return $exit_status
+end
+
+function __voxide_widget
+    set -l line (commandline)
+    commandline ''
+    eval __NAME__ $line </dev/tty
+    commandline -f repaint
+end
+bind \ev __voxide_widget
+bind -M insert \ev __voxide_widget 2>/dev/null
+"#;
+
+/// Prints the shell functions, keybinding and completions for `shell`, defining the command
+/// `name` and `<name>cd` (for --init-shell)
+pub fn print_init_shell(shell: &str, name: &str) -> Result<()> {
+    let functions = match shell {
+        "bash" => BASH,
+        "zsh" => ZSH,
+        "fish" => FISH,
+        _ => {
+            return Err(eyre!(
+                "Unknown shell {shell} (expected one of: {})",
+                SHELLS.join(", ")
+            ))
+        }
+    };
+    let cd_name = format!("{name}cd");
+    print!("{}", functions.replace("__NAME__", name));
+    println!();
+    print!("{}", completion_script(shell, &[name, &cd_name])?);
+    Ok(())
+}
@@ -30,7 +30,7 @@ impl Replacer for LinenoCapturingReplacer<'_> {
 }
 
 impl TransformsApplicator<'_> {
-    pub fn new(transforms: &Vec<(String, String)>) -> TransformsApplicator {
+    pub fn new(transforms: &Vec<(String, String)>) -> TransformsApplicator<'_> {
         TransformsApplicator { transforms }
     }
 
@@ -1,37 +1,78 @@
-/**
- * Allows us to use ${gitroot} and ${home} in replacement strings
- * TODO: refactor to allow easier generalization to allow other environment variables or placeholders
- */
+//! Allows us to use ${gitroot} and ${home} in replacement strings, and in mode `env`/`cwd` values
+//! TODO: refactor to allow easier generalization to allow other environment variables or placeholders
 
 // get git root from git command, in a Lazy way:
 use std::sync::LazyLock;
 use std::process::Command;
 use log::debug;
 use crate::path_util::home_dir;
+use std::path::PathBuf;
 
-static GIT_ROOT: LazyLock<String> = LazyLock::new(|| {
-    let output = Command::new("git")
+static GIT_ROOT: LazyLock<String> = LazyLock::new(|| git_toplevel("git"));
+
+/// The root of the git repo containing the cwd, according to `git`, or "" if not in one (or if
+/// `git` can't be run, e.g. because it isn't installed)
+fn git_toplevel(git: &str) -> String {
+    let output = match Command::new(git)
         .args(["rev-parse", "--show-toplevel"])
         .output()
-        .expect("Failed to execute git command");
+    {
+        Ok(output) => output,
+        Err(err) => {
+            debug!("Failed to run {git}: {err}");
+            return "".to_string();
+        }
+    };
 
     if output.status.success() {
-        String::from_utf8(output.stdout)
-            .expect("Invalid UTF-8 from git command")
+        String::from_utf8_lossy(&output.stdout)
             .trim()
-            .replace("\\", "\\\\") // TOOD: is this all the things we need to rpelace in
-                                   // replacement strings???
-            .replace("$", "\\$")
             .to_string()
     } else {
         debug!("Git command failed: {}", String::from_utf8_lossy(&output.stderr));
         "".to_string()
     }
-});
+}
+
+/// Value for a placeholder, e.g. "gitroot" for ${gitroot}
+fn placeholder_value(name: &str) -> Option<String> {
+    match name {
+        "gitroot" => Some(GIT_ROOT.clone()),
+        "home" => home_dir().ok().map(|path| path.display().to_string()),
+        _ => None,
+    }
+}
+
+// TOOD: is this all the things we need to rpelace in replacement strings???
+fn escape_for_replacement(value: &str) -> String {
+    value.replace("\\", "\\\\").replace("$", "\\$")
+}
+
+/// Value for a placeholder in a replacement string, escaped as transforms always have: ${gitroot}
+/// twice over, and ${home} dropped if there's no home directory
+fn replacement_value(name: &str, git_root: &str, home: Option<PathBuf>) -> Option<String> {
+    match name {
+        "gitroot" => Some(escape_for_replacement(&escape_for_replacement(git_root))),
+        "home" => Some(
+            home.map(|path| escape_for_replacement(&path.display().to_string()))
+                .unwrap_or_default(),
+        ),
+        _ => None,
+    }
+}
 
 // Replace ${gitroot} in replacement strings with the actual git root path, escaped
-// This was done by AI, could likely be improved...
 pub fn process_replacement_string(replacement: &str) -> String {
+    expand(replacement, |name| replacement_value(name, &GIT_ROOT, home_dir().ok()))
+}
+
+/// Replace ${gitroot}, ${home} with their values, unescaped (for env vars, paths, etc.)
+pub fn expand_placeholders(value: &str) -> String {
+    expand(value, placeholder_value)
+}
+
+// This was done by AI, could likely be improved...
+fn expand(replacement: &str, value_of: impl Fn(&str) -> Option<String>) -> String {
     let mut result = String::new();
     let mut chars = replacement.chars().peekable();
 
@@ -43,7 +84,7 @@ pub fn process_replacement_string(replacement: &str) -> String {
                     if let Some('{') = chars.peek() {
                         result.push(next);
                         // Push the rest of the escaped sequence as a literal string
-                        while let Some(next_c) = chars.next() {
+                        for next_c in chars.by_ref() {
                             result.push(next_c);
                             if next_c == '}' {
                                 break;
@@ -61,32 +102,25 @@ pub fn process_replacement_string(replacement: &str) -> String {
             '$' => {
                 if let Some('{') = chars.peek() {
                     // Peek ahead to see if it's the full "${...}" pattern
-                    let mut temp_chars = chars.clone();
+                    let temp_chars = chars.clone();
                     let mut expansion_pattern = String::new();
                     
-                    let mut _char_count = 0;
-                    
-                    while let Some(next_c) = temp_chars.next() {
+                    for next_c in temp_chars {
                         expansion_pattern.push(next_c);
-                        _char_count += 1;
                         if next_c == '}' {
                             break;
                         }
                     }
                     
-                    if expansion_pattern.starts_with("{gitroot}") {
+                    let name = expansion_pattern
+                        .strip_prefix('{')
+                        .and_then(|rest| rest.strip_suffix('}'));
+                    if let Some(value) = name.and_then(&value_of) {
                         // Consume the characters we just peeked at
-                        for _ in 0..expansion_pattern.len() {
-                            chars.next();
-                        }
-                        result.push_str(GIT_ROOT.replace("\\", "\\\\").replace("$", "\\$").as_str());
-                    } else if expansion_pattern.starts_with("{home}") {
-                        for _ in 0..expansion_pattern.len() {
+                        for _ in 0..expansion_pattern.chars().count() {
                             chars.next();
                         }
-                        if let Ok(home_path) = home_dir() {
-                            result.push_str(home_path.display().to_string().replace("\\", "\\\\").replace("$", "\\$").as_str());
-                        }
+                        result.push_str(&value);
                     } else {
                         result.push(c);
                     }
@@ -100,4 +134,29 @@ pub fn process_replacement_string(replacement: &str) -> String {
     result
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replacement_values() {
+        let expand = |replacement: &str, home: Option<&str>| {
+            expand(replacement, |name| {
+                replacement_value(name, r"/a\b$c", home.map(PathBuf::from))
+            })
+        };
+        assert_eq!(expand("${gitroot}/x", None), r"/a\\\\b\\\$c/x");
+        assert_eq!(expand("${home}/x", Some("/h$")), r"/h\$/x");
+        assert_eq!(expand("${home}/x", None), "/x");
+        assert_eq!(expand(r"\${gitroot} ${other} $1", None), r"${gitroot} ${other} $1");
+        assert_eq!(
+            expand_placeholders("${other}/${gitroot}"),
+            format!("${{other}}/{}", *GIT_ROOT)
+        );
+    }
 
+    #[test]
+    fn test_git_toplevel_without_git() {
+        assert_eq!(git_toplevel("voxide-no-such-git"), "");
+    }
+}